@@ -0,0 +1,144 @@
+use crate::EMPTY_REF;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Ids below `2 * dense.len() + DENSE_GAP` grow the dense table, larger ones go to `sparse`.
+const DENSE_GAP: usize = 64;
+
+/// Maps the id of a value to its node index.
+///
+/// Ids are not required to be dense. Small ids live in a flat table, so a one off huge id
+/// costs a map entry instead of a huge allocation.
+pub(crate) struct IdIndex<V> {
+    id_fn: fn(&V) -> usize,
+    dense: Vec<u32>,
+    // only ids that are not less than `dense.len()`
+    sparse: BTreeMap<usize, u32>,
+}
+
+impl<V> IdIndex<V> {
+    #[inline]
+    pub(crate) fn new(id_fn: fn(&V) -> usize) -> Self {
+        Self {
+            id_fn,
+            dense: Vec::new(),
+            sparse: BTreeMap::new(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn empty_like(&self) -> Self {
+        Self::new(self.id_fn)
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, id: usize) -> u32 {
+        if let Some(&index) = self.dense.get(id) {
+            index
+        } else {
+            self.sparse.get(&id).copied().unwrap_or(EMPTY_REF)
+        }
+    }
+
+    #[inline]
+    pub(crate) fn set(&mut self, val: &V, index: u32) {
+        let id = (self.id_fn)(val);
+        if id < self.dense.len() {
+            self.dense[id] = index;
+        } else if id - self.dense.len() <= self.dense.len() + DENSE_GAP {
+            self.grow_dense(id + 1);
+            self.dense[id] = index;
+        } else {
+            self.sparse.insert(id, index);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn id(&self, val: &V) -> usize {
+        (self.id_fn)(val)
+    }
+
+    #[inline]
+    pub(crate) fn remove(&mut self, val: &V) {
+        self.remove_id((self.id_fn)(val));
+    }
+
+    #[inline]
+    fn remove_id(&mut self, id: usize) {
+        if let Some(index) = self.dense.get_mut(id) {
+            *index = EMPTY_REF;
+        } else {
+            self.sparse.remove(&id);
+        }
+    }
+
+    // `val` at `index` was stored under `old_id`, the old entry is kept if another value took it
+    #[inline]
+    pub(crate) fn replace(&mut self, old_id: usize, val: &V, index: u32) {
+        if (self.id_fn)(val) == old_id {
+            return;
+        }
+        if self.get(old_id) == index {
+            self.remove_id(old_id);
+        }
+        self.set(val, index);
+    }
+
+    #[inline]
+    pub(crate) fn clear(&mut self) {
+        self.dense.clear();
+        self.sparse.clear();
+    }
+
+    fn grow_dense(&mut self, len: usize) {
+        self.dense.resize(len, EMPTY_REF);
+        if self.sparse.is_empty() {
+            return;
+        }
+        // sparse ids now covered by the table move into it
+        let tail = self.sparse.split_off(&len);
+        for (id, index) in core::mem::replace(&mut self.sparse, tail) {
+            self.dense[id] = index;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::EMPTY_REF;
+    use crate::id_index::IdIndex;
+
+    #[test]
+    fn test_00() {
+        let mut ids = IdIndex::new(|&id: &usize| id);
+        ids.set(&usize::MAX, 1);
+        ids.set(&(1 << 40), 2);
+        ids.set(&3, 3);
+
+        assert!(ids.dense.len() < 1024);
+        assert_eq!(ids.get(usize::MAX), 1);
+        assert_eq!(ids.get(1 << 40), 2);
+        assert_eq!(ids.get(3), 3);
+        assert_eq!(ids.get(4), EMPTY_REF);
+
+        ids.remove(&usize::MAX);
+        assert_eq!(ids.get(usize::MAX), EMPTY_REF);
+    }
+
+    #[test]
+    fn test_01() {
+        let mut ids = IdIndex::new(|&id: &usize| id);
+        ids.set(&1000, 1000);
+        assert!(ids.dense.is_empty());
+
+        for id in (0..1100).filter(|&id| id != 1000) {
+            ids.set(&id, id as u32);
+        }
+
+        // the table grew over the sparse id and took it over
+        assert!(ids.sparse.is_empty());
+        for id in 0..1100 {
+            assert_eq!(ids.get(id), id as u32);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::id_index::IdIndex;
+use crate::key::tree::KeyExpTree;
+use crate::{EMPTY_REF, Expiration, ExpiredKey, HasId};
+
+impl<K: ExpiredKey<E>, E: Expiration, V: Copy + HasId> KeyExpTree<K, E, V> {
+    /// Creates a tree that can also find a value by `HasId::id`. Ids do not have to be dense.
+    #[inline]
+    pub fn with_id_index(capacity: usize) -> Self {
+        let mut tree = Self::new(capacity);
+        tree.ids = Some(IdIndex::new(V::id));
+        tree
+    }
+
+    /// Creates a tree with both an id index and a history, see `with_history`.
+    #[inline]
+    pub fn with_history_and_id_index(capacity: usize) -> Self {
+        let mut tree = Self::with_history(capacity);
+        tree.ids = Some(IdIndex::new(V::id));
+        tree
+    }
+
+    /// Node index of the value with `id`, or `None` if the tree has no id index.
+    #[inline]
+    pub fn position_of(&self, id: usize) -> Option<u32> {
+        let index = self.ids.as_ref()?.get(id);
+        if index == EMPTY_REF { None } else { Some(index) }
+    }
+
+    #[inline]
+    pub fn remove_by_id(&mut self, time: E, id: usize) -> Option<V> {
+        let index = self.position_of(id)?;
+        let node = self.node(index);
+        let is_alive = node.is_not_expired(time);
+        let entity = node.entity;
+        self.delete_index(index);
+        if let Some(history) = &mut self.history {
+            history.remove(entity.key, time);
+        }
+        if is_alive { Some(entity.val) } else { None }
+    }
+
+    #[inline]
+    pub fn next_by_id(&mut self, time: E, id: usize) -> Option<V> {
+        self.neighbor_by_id(time, id, Self::index_after)
+    }
+
+    #[inline]
+    pub fn prev_by_id(&mut self, time: E, id: usize) -> Option<V> {
+        self.neighbor_by_id(time, id, Self::index_before)
+    }
+
+    #[inline]
+    fn neighbor_by_id<F>(&mut self, time: E, id: usize, step: F) -> Option<V>
+    where
+        F: Fn(&Self, u32) -> u32,
+    {
+        let index = self.position_of(id)?;
+        if !self.node(index).is_not_expired(time) {
+            self.delete_index(index);
+            return None;
+        }
+        loop {
+            let neighbor = step(self, index);
+            if neighbor == EMPTY_REF {
+                return None;
+            }
+            let node = self.node(neighbor);
+            if node.is_not_expired(time) {
+                return Some(node.entity.val);
+            }
+            self.delete_index(neighbor);
+        }
+    }
+}
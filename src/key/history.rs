@@ -0,0 +1,147 @@
+use crate::key::tree::KeyExpTree;
+use crate::{Expiration, ExpiredKey};
+use alloc::collections::BTreeMap;
+use alloc::collections::btree_map;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy)]
+struct Version<E, V> {
+    start: E,
+    end: E,
+    val: V,
+}
+
+impl<E: Expiration, V: Copy> Version<E, V> {
+    #[inline(always)]
+    fn is_alive(&self, time: E) -> bool {
+        self.start <= time && time < self.end
+    }
+}
+
+/// The recorded past of a `KeyExpTree` built with `with_history`.
+pub struct History<K, E, V> {
+    versions: BTreeMap<K, Vec<Version<E, V>>>,
+}
+
+impl<K: ExpiredKey<E>, E: Expiration, V: Copy> History<K, E, V> {
+    #[inline]
+    pub(super) fn new() -> Self {
+        Self {
+            versions: BTreeMap::new(),
+        }
+    }
+
+    #[inline]
+    pub(super) fn insert(&mut self, key: K, val: V, time: E) {
+        let version = Version {
+            start: time,
+            end: key.expiration(),
+            val,
+        };
+        match self.versions.entry(key) {
+            btree_map::Entry::Vacant(entry) => {
+                entry.insert(vec![version]);
+            }
+            btree_map::Entry::Occupied(mut entry) => entry.get_mut().push(version),
+        }
+    }
+
+    #[inline]
+    pub(super) fn remove(&mut self, key: K, time: E) {
+        if let Some(version) = self.versions.get_mut(&key).and_then(|list| list.last_mut()) {
+            version.end = version.end.min(time);
+        }
+    }
+
+    #[inline]
+    pub(super) fn renew(&mut self, new_key: K, time: E) {
+        let Some(list) = self.versions.get_mut(&new_key) else {
+            return;
+        };
+        let Some(last) = list.last_mut() else {
+            return;
+        };
+        let val = last.val;
+        last.end = last.end.min(time);
+        list.push(Version {
+            start: time,
+            end: new_key.expiration(),
+            val,
+        });
+    }
+
+    #[inline]
+    pub(super) fn clear(&mut self) {
+        self.versions.clear();
+    }
+
+    #[inline]
+    fn value_at(versions: &[Version<E, V>], time: E) -> Option<V> {
+        versions.iter().rev().find(|v| v.is_alive(time)).map(|v| v.val)
+    }
+}
+
+pub struct HistoryIterator<'a, K, E, V> {
+    iter: btree_map::Iter<'a, K, Vec<Version<E, V>>>,
+    time: E,
+}
+
+impl<K: ExpiredKey<E>, E: Expiration, V: Copy> Iterator for HistoryIterator<'_, K, E, V> {
+    type Item = V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        for (_, versions) in self.iter.by_ref() {
+            if let Some(val) = History::<K, E, V>::value_at(versions, self.time) {
+                return Some(val);
+            }
+        }
+        None
+    }
+}
+
+impl<K: ExpiredKey<E>, E: Expiration, V: Copy> History<K, E, V> {
+    #[inline]
+    pub fn get_value_at(&self, past_time: E, key: K) -> Option<V> {
+        Self::value_at(self.versions.get(&key)?, past_time)
+    }
+
+    #[inline]
+    pub fn first_less_at(&self, past_time: E, default: V, key: K) -> V {
+        self.versions
+            .range(..key)
+            .rev()
+            .find_map(|(_, versions)| Self::value_at(versions, past_time))
+            .unwrap_or(default)
+    }
+
+    #[inline]
+    pub fn iter_at(&self, past_time: E) -> HistoryIterator<'_, K, E, V> {
+        HistoryIterator {
+            iter: self.versions.iter(),
+            time: past_time,
+        }
+    }
+}
+
+impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
+    /// Creates a tree that also records when every entry was inserted and removed,
+    /// so the queries of `history` can look at earlier states.
+    ///
+    /// Old versions are never pruned, the history grows with every insert, removal and
+    /// renewal. `clear` has no time to close the recorded entries at, so it drops the history
+    /// too.
+    #[inline]
+    pub fn with_history(capacity: usize) -> Self {
+        let mut tree = Self::new(capacity);
+        tree.history = Some(History::new());
+        tree
+    }
+
+    /// The recorded past, or `None` if the tree was not created with a history.
+    #[inline]
+    pub fn history(&self) -> Option<&History<K, E, V>> {
+        self.history.as_ref()
+    }
+}
@@ -0,0 +1,329 @@
+use crate::key::array::IntoArray;
+use crate::key::entity::Entity;
+use crate::key::exp::KeyExpCollection;
+use crate::key::tree::KeyExpTree;
+use crate::{EMPTY_REF, Expiration, ExpiredKey};
+use alloc::collections::{BTreeSet, BinaryHeap};
+use alloc::vec::Vec;
+use core::cmp::{Ordering, Reverse};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum EventKind {
+    Expire,
+    Swap,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Event<E> {
+    time: E,
+    kind: EventKind,
+    index: u32,
+    stamp: u32,
+    next: u32,
+    next_stamp: u32,
+}
+
+/// A `KeyExpTree` for keys whose order changes over time.
+///
+/// `certificate_failure(a, b)` is called for every adjacent pair `a < b` and must return
+/// the first time when `a < b` no longer holds. When time advances past it the two entries
+/// swap places, so the order of `K` only has to be valid at the time of a query or insert.
+pub struct KineticTree<K, E, V, F> {
+    tree: KeyExpTree<K, E, V>,
+    certificate_failure: F,
+    events: BinaryHeap<Reverse<Event<E>>>,
+    stamps: Vec<u32>,
+    // serial number of the entity in each node, entities move between nodes on a swap
+    serials: Vec<u32>,
+    next_serial: u32,
+    // entity pairs already swapped at `swapped_at`
+    swapped: BTreeSet<(u32, u32)>,
+    swapped_at: Option<E>,
+    time: Option<E>,
+}
+
+impl<K, E, V, F> KineticTree<K, E, V, F>
+where
+    K: ExpiredKey<E>,
+    E: Expiration,
+    V: Copy,
+    F: Fn(&K, &K) -> Option<E>,
+{
+    #[inline]
+    pub fn new(capacity: usize, certificate_failure: F) -> Self {
+        Self {
+            tree: KeyExpTree::new(capacity),
+            certificate_failure,
+            events: BinaryHeap::with_capacity(capacity),
+            stamps: Vec::with_capacity(capacity),
+            serials: Vec::with_capacity(capacity),
+            next_serial: 0,
+            swapped: BTreeSet::new(),
+            swapped_at: None,
+            time: None,
+        }
+    }
+
+    /// Processes all expirations and order swaps up to `time` inclusive.
+    pub fn advance_to(&mut self, time: E) {
+        while let Some(&Reverse(event)) = self.events.peek() {
+            if event.time > time {
+                break;
+            }
+            self.events.pop();
+            self.time = Some(event.time);
+            match event.kind {
+                EventKind::Expire => {
+                    if self.is_valid_expire(&event) {
+                        self.delete(event.index);
+                    }
+                }
+                EventKind::Swap => {
+                    if self.is_valid_swap(&event) {
+                        self.swap(event.index, event.next);
+                    }
+                }
+            }
+        }
+        self.time = Some(self.time.map_or(time, |t| t.max(time)));
+    }
+
+    /// Same as `KeyExpTree::update_expiration`.
+    #[inline]
+    pub fn update_expiration(&mut self, time: E, key: K, new_key: K) -> bool {
+        self.advance_to(time);
+        let index = self.tree.find_index(time, key);
+        if index == EMPTY_REF {
+            return false;
+        }
+        self.tree.node_mut(index).entity.key = new_key;
+        // the old expire event no longer matches the key and is skipped
+        self.schedule_expire(index);
+        true
+    }
+
+    /// Same as `KeyExpTree::first_less_where`.
+    #[inline]
+    pub fn first_less_where<P>(&mut self, time: E, default: V, key: K, pred: P) -> V
+    where
+        P: Fn(V) -> bool,
+    {
+        self.advance_to(time);
+        self.tree.first_less_where(time, default, key, pred)
+    }
+
+    /// Same as `KeyExpTree::first_greater_where`.
+    #[inline]
+    pub fn first_greater_where<P>(&mut self, time: E, default: V, key: K, pred: P) -> V
+    where
+        P: Fn(V) -> bool,
+    {
+        self.advance_to(time);
+        self.tree.first_greater_where(time, default, key, pred)
+    }
+
+    #[inline]
+    fn is_valid_expire(&self, event: &Event<E>) -> bool {
+        self.stamp(event.index) == event.stamp
+            && self.tree.node(event.index).entity.key.expiration() == event.time
+    }
+
+    #[inline]
+    fn is_valid_swap(&self, event: &Event<E>) -> bool {
+        self.stamp(event.index) == event.stamp
+            && self.stamp(event.next) == event.next_stamp
+            && self.tree.index_after(event.index) == event.next
+    }
+
+    #[inline]
+    fn stamp(&self, index: u32) -> u32 {
+        self.stamps.get(index as usize).copied().unwrap_or(0)
+    }
+
+    #[inline]
+    fn bump_stamp(&mut self, index: u32) {
+        let i = index as usize;
+        if i >= self.stamps.len() {
+            self.stamps.resize(i + 1, 0);
+        }
+        self.stamps[i] = self.stamps[i].wrapping_add(1);
+    }
+
+    #[inline]
+    fn assign_serial(&mut self, index: u32) {
+        let i = index as usize;
+        if i >= self.serials.len() {
+            self.serials.resize(i + 1, 0);
+        }
+        self.serials[i] = self.next_serial;
+        self.next_serial = self.next_serial.wrapping_add(1);
+    }
+
+    #[inline]
+    fn serial_pair(&self, a: u32, b: u32) -> (u32, u32) {
+        let a = self.serials[a as usize];
+        let b = self.serials[b as usize];
+        (a.min(b), a.max(b))
+    }
+
+    #[inline]
+    fn schedule_expire(&mut self, index: u32) {
+        let time = self.tree.node(index).entity.key.expiration();
+        self.events.push(Reverse(Event {
+            time,
+            kind: EventKind::Expire,
+            index,
+            stamp: self.stamp(index),
+            next: EMPTY_REF,
+            next_stamp: 0,
+        }));
+    }
+
+    #[inline]
+    fn schedule_swap(&mut self, index: u32, next: u32) {
+        if index == EMPTY_REF || next == EMPTY_REF {
+            return;
+        }
+        let a = &self.tree.node(index).entity.key;
+        let b = &self.tree.node(next).entity.key;
+        let Some(mut time) = (self.certificate_failure)(a, b) else {
+            return;
+        };
+        // an already failed certificate is fixed right away, but only once: two entries can
+        // not cross twice at the same time, a degenerate pair would swap back and forth forever
+        if let Some(now) = self.time
+            && time <= now
+        {
+            if self.swapped_at == Some(now) && self.swapped.contains(&self.serial_pair(index, next)) {
+                return;
+            }
+            time = now;
+        }
+        self.events.push(Reverse(Event {
+            time,
+            kind: EventKind::Swap,
+            index,
+            stamp: self.stamp(index),
+            next,
+            next_stamp: self.stamp(next),
+        }));
+    }
+
+    #[inline]
+    fn schedule_around(&mut self, index: u32) {
+        self.schedule_expire(index);
+        let prev = self.tree.index_before(index);
+        let next = self.tree.index_after(index);
+        self.schedule_swap(prev, index);
+        self.schedule_swap(index, next);
+    }
+
+    fn swap(&mut self, index: u32, next: u32) {
+        if self.swapped_at != self.time {
+            self.swapped.clear();
+            self.swapped_at = self.time;
+        }
+        self.swapped.insert(self.serial_pair(index, next));
+        self.serials.swap(index as usize, next as usize);
+        self.tree.swap_entities(index, next);
+        self.bump_stamp(index);
+        self.bump_stamp(next);
+        self.schedule_expire(index);
+        self.schedule_expire(next);
+        let prev = self.tree.index_before(index);
+        let after = self.tree.index_after(next);
+        self.schedule_swap(prev, index);
+        self.schedule_swap(index, next);
+        self.schedule_swap(next, after);
+    }
+
+    fn delete(&mut self, index: u32) {
+        let prev = self.tree.index_before(index);
+        let next = self.tree.index_after(index);
+        self.tree.delete_index(index);
+        self.bump_stamp(index);
+        self.schedule_swap(prev, next);
+    }
+}
+
+impl<K, E, V, F> KeyExpCollection<K, E, V> for KineticTree<K, E, V, F>
+where
+    K: ExpiredKey<E>,
+    E: Expiration,
+    V: Copy,
+    F: Fn(&K, &K) -> Option<E>,
+{
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    #[inline]
+    fn insert(&mut self, key: K, val: V, time: E) {
+        debug_assert!(key.expiration() >= time, "The value is already expired");
+        self.advance_to(time);
+        let index = self.tree.insert_entity(Entity::new(key, val), time);
+        self.bump_stamp(index);
+        self.assign_serial(index);
+        self.schedule_around(index);
+    }
+
+    #[inline]
+    fn get_value(&mut self, time: E, key: K) -> Option<V> {
+        self.advance_to(time);
+        self.tree.get_value(time, key)
+    }
+
+    #[inline]
+    fn first_less(&mut self, time: E, default: V, key: K) -> V {
+        self.advance_to(time);
+        self.tree.first_less(time, default, key)
+    }
+
+    #[inline]
+    fn first_less_by<P>(&mut self, time: E, default: V, f: P) -> V
+    where
+        P: Fn(K) -> Ordering,
+    {
+        self.advance_to(time);
+        self.tree.first_less_by(time, default, f)
+    }
+
+    #[inline]
+    fn first_less_or_equal(&mut self, time: E, default: V, key: K) -> V {
+        self.advance_to(time);
+        self.tree.first_less_or_equal(time, default, key)
+    }
+
+    #[inline]
+    fn first_less_or_equal_by<P>(&mut self, time: E, default: V, f: P) -> V
+    where
+        P: Fn(K) -> Ordering,
+    {
+        self.advance_to(time);
+        self.tree.first_less_or_equal_by(time, default, f)
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.tree.clear();
+        self.events.clear();
+        self.swapped.clear();
+        self.swapped_at = None;
+        self.time = None;
+    }
+}
+
+impl<K, E, V, F> IntoArray<E, V> for KineticTree<K, E, V, F>
+where
+    K: ExpiredKey<E>,
+    E: Expiration,
+    V: Copy,
+    F: Fn(&K, &K) -> Option<E>,
+{
+    #[inline]
+    fn into_ordered_vec(mut self, time: E) -> Vec<V> {
+        self.advance_to(time);
+        self.tree.into_ordered_vec(time)
+    }
+}
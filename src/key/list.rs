@@ -26,6 +26,60 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpList<K, E, V> {
             self.buffer.reserve(additional);
         }
     }
+
+    /// Same as `KeyExpTree::update_expiration`.
+    #[inline]
+    pub fn update_expiration(&mut self, time: E, key: K, new_key: K) -> bool {
+        debug_assert!(key == new_key, "The new key must keep the same order");
+        self.clear_expired(time);
+        if let Ok(index) = self.buffer.binary_search_by_key(&key, |e| e.key) {
+            unsafe { self.buffer.get_unchecked_mut(index) }.key = new_key;
+            // min_exp is only a lower bound, a renewed key can not break it
+            self.min_exp = self.min_exp.min(new_key.expiration());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Same as `KeyExpTree::first_less_where`.
+    #[inline]
+    pub fn first_less_where<P>(&mut self, time: E, default: V, key: K, pred: P) -> V
+    where
+        P: Fn(V) -> bool,
+    {
+        self.clear_expired(time);
+        let index = self
+            .buffer
+            .binary_search_by(|e| e.key.cmp(&key))
+            .unwrap_or_else(|index| index);
+
+        self.buffer[..index]
+            .iter()
+            .rev()
+            .map(|e| e.val)
+            .find(|&val| pred(val))
+            .unwrap_or(default)
+    }
+
+    /// Same as `KeyExpTree::first_greater_where`.
+    #[inline]
+    pub fn first_greater_where<P>(&mut self, time: E, default: V, key: K, pred: P) -> V
+    where
+        P: Fn(V) -> bool,
+    {
+        self.clear_expired(time);
+        let index = match self.buffer.binary_search_by(|e| e.key.cmp(&key)) {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        };
+
+        self.buffer[index..]
+            .iter()
+            .map(|e| e.val)
+            .find(|&val| pred(val))
+            .unwrap_or(default)
+    }
 }
 
 impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpCollection<K, E, V> for KeyExpList<K, E, V> {
@@ -1,7 +1,10 @@
 pub mod array;
+mod by_id;
 mod entity;
 pub mod exp;
+pub mod history;
 pub mod iter;
+pub mod kinetic;
 pub mod list;
 mod node;
 mod pool;
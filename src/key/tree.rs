@@ -1,5 +1,7 @@
+use crate::id_index::IdIndex;
 use crate::key::entity::Entity;
 use crate::key::exp::KeyExpCollection;
+use crate::key::history::History;
 use crate::key::node::{Color, Node};
 use crate::key::pool::Pool;
 use crate::{EMPTY_REF, Expiration, ExpiredKey};
@@ -9,6 +11,8 @@ use core::marker::PhantomData;
 pub struct KeyExpTree<K, E, V> {
     pub(super) store: Pool<K, E, V>,
     pub(super) root: u32,
+    pub(super) ids: Option<IdIndex<V>>,
+    pub(super) history: Option<History<K, E, V>>,
     phantom_data: PhantomData<E>,
 }
 
@@ -23,6 +27,8 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
         Self {
             store,
             root: EMPTY_REF,
+            ids: None,
+            history: None,
             phantom_data: Default::default(),
         }
     }
@@ -34,6 +40,56 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
             self.store.reserve(additional)
         }
     }
+
+    /// Replaces the stored `key` with `new_key`, which must keep the same order.
+    /// Returns false if `key` is not stored or is already expired at `time`.
+    ///
+    /// The expiration lives inside the key and `ExpiredKey` can only read it, so the caller
+    /// builds the renewed key.
+    #[inline]
+    pub fn update_expiration(&mut self, time: E, key: K, new_key: K) -> bool {
+        debug_assert!(key == new_key, "The new key must keep the same order");
+        debug_assert!(new_key.expiration() >= time, "The new key is already expired");
+        let index = self.find_index(time, key);
+        if index == EMPTY_REF {
+            return false;
+        }
+        self.node_mut(index).entity.key = new_key;
+        if let Some(history) = &mut self.history {
+            history.renew(new_key, time);
+        }
+        true
+    }
+
+    /// The last value with a key less than `key` that matches `pred`, or `default`.
+    #[inline]
+    pub fn first_less_where<P>(&mut self, time: E, default: V, key: K, pred: P) -> V
+    where
+        P: Fn(V) -> bool,
+    {
+        let index = self.find_first_less_index(time, key);
+        let index = self.walk_before_while_not(time, index, pred);
+        if index == EMPTY_REF {
+            default
+        } else {
+            self.node(index).entity.val
+        }
+    }
+
+    /// The first value with a key greater than `key` that matches `pred`, or `default`.
+    #[inline]
+    pub fn first_greater_where<P>(&mut self, time: E, default: V, key: K, pred: P) -> V
+    where
+        P: Fn(V) -> bool,
+    {
+        let index = self.find_first_greater_index(time, key);
+        let index = self.walk_after_while_not(time, index, pred);
+        if index == EMPTY_REF {
+            default
+        } else {
+            self.node(index).entity.val
+        }
+    }
 }
 
 impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpCollection<K, E, V> for KeyExpTree<K, E, V> {
@@ -45,6 +101,9 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpCollection<K, E, V> for Key
     #[inline(always)]
     fn insert(&mut self, key: K, val: V, time: E) {
         debug_assert!(key.expiration() >= time, "The value is already expired");
+        if let Some(history) = &mut self.history {
+            history.insert(key, val, time);
+        }
         self.insert_entity(Entity::new(key, val), time);
     }
 
@@ -79,11 +138,17 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpCollection<K, E, V> for Key
     }
 
     fn clear(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
         if self.root == EMPTY_REF {
             return;
         }
         self.store.put_back(self.root);
         self.root = EMPTY_REF;
+        if let Some(ids) = &mut self.ids {
+            ids.clear();
+        }
 
         let mut n = 1;
         while n > 0 {
@@ -168,6 +233,23 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
         index
     }
 
+    #[inline]
+    fn register_id(&mut self, index: u32) {
+        if let Some(ids) = &mut self.ids {
+            let node = unsafe { self.store.buffer.get_unchecked(index as usize) };
+            ids.set(&node.entity.val, index);
+        }
+    }
+
+    #[inline]
+    pub(super) fn swap_entities(&mut self, a: u32, b: u32) {
+        let entity = self.node(a).entity;
+        self.node_mut(a).entity = self.node(b).entity;
+        self.node_mut(b).entity = entity;
+        self.register_id(a);
+        self.register_id(b);
+    }
+
     #[inline]
     fn create_nil_node(&mut self, parent: u32) {
         let node = self.node_mut(NIL_INDEX);
@@ -178,7 +260,7 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
     }
 
     #[inline]
-    fn insert_root(&mut self, entity: Entity<K, E, V>) {
+    fn insert_root(&mut self, entity: Entity<K, E, V>) -> u32 {
         let new_index = self.store.get_free_index();
         let new_node = self.node_mut(new_index);
         new_node.parent = EMPTY_REF;
@@ -187,22 +269,35 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
         new_node.color = Color::Black;
         new_node.entity = entity;
         self.root = new_index;
+        self.register_id(new_index);
+
+        new_index
     }
 
     #[inline]
     fn search_value(&mut self, time: E, key: K) -> Option<V> {
+        let index = self.find_index(time, key);
+        if index == EMPTY_REF {
+            None
+        } else {
+            Some(self.node(index).entity.val)
+        }
+    }
+
+    #[inline]
+    pub(super) fn find_index(&mut self, time: E, key: K) -> u32 {
         let mut index = self.expire_root(time);
 
         while index != EMPTY_REF {
             let entity = self.node(index).entity;
-            match entity.key.cmp(&key) {
-                Ordering::Equal => return Some(entity.val),
+            match key.cmp(&entity.key) {
+                Ordering::Equal => return index,
                 Ordering::Less => index = self.expire_left(index, time),
                 Ordering::Greater => index = self.expire_right(index, time),
             }
         }
 
-        None
+        EMPTY_REF
     }
 
     #[inline]
@@ -223,6 +318,78 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
         result
     }
 
+    #[inline]
+    fn find_first_less_index(&mut self, time: E, key: K) -> u32 {
+        let mut index = self.expire_root(time);
+        let mut result = EMPTY_REF;
+        while index != EMPTY_REF {
+            if self.node(index).entity.key < key {
+                result = index;
+                index = self.expire_right(index, time);
+            } else {
+                index = self.expire_left(index, time);
+            }
+        }
+
+        result
+    }
+
+    #[inline]
+    fn find_first_greater_index(&mut self, time: E, key: K) -> u32 {
+        let mut index = self.expire_root(time);
+        let mut result = EMPTY_REF;
+        while index != EMPTY_REF {
+            if self.node(index).entity.key > key {
+                result = index;
+                index = self.expire_left(index, time);
+            } else {
+                index = self.expire_right(index, time);
+            }
+        }
+
+        result
+    }
+
+    fn walk_before_while_not<P>(&mut self, time: E, mut index: u32, pred: P) -> u32
+    where
+        P: Fn(V) -> bool,
+    {
+        // index is alive, the search path is already expired
+        while index != EMPTY_REF {
+            if pred(self.node(index).entity.val) {
+                return index;
+            }
+            let mut next = self.index_before(index);
+            while next != EMPTY_REF && !self.node(next).is_not_expired(time) {
+                self.delete_index(next);
+                next = self.index_before(index);
+            }
+            index = next;
+        }
+
+        EMPTY_REF
+    }
+
+    fn walk_after_while_not<P>(&mut self, time: E, mut index: u32, pred: P) -> u32
+    where
+        P: Fn(V) -> bool,
+    {
+        // index is alive, the search path is already expired
+        while index != EMPTY_REF {
+            if pred(self.node(index).entity.val) {
+                return index;
+            }
+            let mut next = self.index_after(index);
+            while next != EMPTY_REF && !self.node(next).is_not_expired(time) {
+                self.delete_index(next);
+                next = self.index_after(index);
+            }
+            index = next;
+        }
+
+        EMPTY_REF
+    }
+
     #[inline]
     fn search_first_less_or_equal(&mut self, time: E, default: V, key: K) -> V {
         let mut index = self.expire_root(time);
@@ -286,11 +453,10 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
     }
 
     #[inline]
-    fn insert_entity(&mut self, entity: Entity<K, E, V>, time: E) {
+    pub(super) fn insert_entity(&mut self, entity: Entity<K, E, V>, time: E) -> u32 {
         let mut index = self.expire_root(time);
         if index == EMPTY_REF {
-            self.insert_root(entity);
-            return;
+            return self.insert_root(entity);
         }
 
         let key = entity.key;
@@ -300,14 +466,12 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
             if key < self.node(index).entity.key {
                 index = self.expire_left(index, time);
                 if index == EMPTY_REF {
-                    self.insert_as_left(entity, p_index);
-                    return;
+                    return self.insert_as_left(entity, p_index);
                 }
             } else {
                 index = self.expire_right(index, time);
                 if index == EMPTY_REF {
-                    self.insert_as_right(entity, p_index);
-                    return;
+                    return self.insert_as_right(entity, p_index);
                 }
             }
         }
@@ -322,12 +486,13 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
         new_node.right = EMPTY_REF;
         new_node.color = Color::Red;
         new_node.entity = entity;
+        self.register_id(new_index);
 
         new_index
     }
 
     #[inline]
-    fn insert_as_left(&mut self, entity: Entity<K, E, V>, p_index: u32) {
+    fn insert_as_left(&mut self, entity: Entity<K, E, V>, p_index: u32) -> u32 {
         let new_index = self.insert_new(entity, p_index);
 
         let parent = self.node_mut(p_index);
@@ -336,10 +501,12 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
         if parent.color == Color::Red {
             self.fix_red_black_properties_after_insert(new_index, p_index);
         }
+
+        new_index
     }
 
     #[inline]
-    fn insert_as_right(&mut self, entity: Entity<K, E, V>, p_index: u32) {
+    fn insert_as_right(&mut self, entity: Entity<K, E, V>, p_index: u32) -> u32 {
         let new_index = self.insert_new(entity, p_index);
 
         let parent = self.node_mut(p_index);
@@ -348,6 +515,8 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
         if parent.color == Color::Red {
             self.fix_red_black_properties_after_insert(new_index, p_index);
         }
+
+        new_index
     }
 
     fn fix_red_black_properties_after_insert(&mut self, n_index: u32, p_origin: u32) {
@@ -484,38 +653,80 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
         i
     }
 
-    pub(super) fn delete_index(&mut self, index: u32) {
-        // Node has zero or one child
-        let mut delete_index = index;
+    #[inline]
+    fn find_right_maximum(&self, mut i: u32) -> u32 {
+        while self.node(i).right != EMPTY_REF {
+            i = self.node(i).right;
+        }
+        i
+    }
 
+    #[inline]
+    pub(super) fn index_after(&self, mut index: u32) -> u32 {
         let node = self.node(index);
-        let mut nd_left = node.left;
-        let mut nd_right = node.right;
-        let mut nd_parent = node.parent;
-        let mut nd_color = node.color;
+        if node.right != EMPTY_REF {
+            return self.find_left_minimum(node.right);
+        }
+        // find first parent where we not right
+        let mut parent_index = node.parent;
+        while parent_index != EMPTY_REF {
+            let parent = self.node(parent_index);
+            if parent.right != index {
+                break;
+            }
+            index = parent_index;
+            parent_index = parent.parent;
+        }
+        parent_index
+    }
 
-        // if two children replace node with it left minimum
-        if nd_left != EMPTY_REF && nd_right != EMPTY_REF {
-            let successor_index = self.find_left_minimum(nd_right);
-            let successor = self.node(successor_index);
-            let entity = successor.entity;
-            nd_parent = successor.parent;
-            nd_left = successor.left;
-            nd_right = successor.right;
-            nd_color = successor.color;
+    #[inline]
+    pub(super) fn index_before(&self, mut index: u32) -> u32 {
+        let node = self.node(index);
+        if node.left != EMPTY_REF {
+            return self.find_right_maximum(node.left);
+        }
+        // find first parent where we not left
+        let mut parent_index = node.parent;
+        while parent_index != EMPTY_REF {
+            let parent = self.node(parent_index);
+            if parent.left != index {
+                break;
+            }
+            index = parent_index;
+            parent_index = parent.parent;
+        }
+        parent_index
+    }
 
-            self.node_mut(index).entity = entity;
+    pub(super) fn delete_index(&mut self, index: u32) {
+        if let Some(ids) = &mut self.ids {
+            let val = unsafe { self.store.buffer.get_unchecked(index as usize) }
+                .entity
+                .val;
+            ids.remove(&val);
+        }
 
-            delete_index = successor_index;
+        // if two children relink node with it left minimum, so indices of other nodes stay valid
+        let node = self.node(index);
+        if node.left != EMPTY_REF && node.right != EMPTY_REF {
+            let successor_index = self.find_left_minimum(node.right);
+            self.swap_with_successor(index, successor_index);
         }
 
+        let node = self.node(index);
+        let nd_left = node.left;
+        let nd_right = node.right;
+        let nd_parent = node.parent;
+        let nd_color = node.color;
+
         // only one child can be!
 
         if nd_left != EMPTY_REF {
-            self.replace_parents_child(nd_parent, delete_index, nd_left);
+            self.replace_parents_child(nd_parent, index, nd_left);
             self.fix_red_black_properties_after_delete(nd_left);
         } else if nd_right != EMPTY_REF {
-            self.replace_parents_child(nd_parent, delete_index, nd_right);
+            self.replace_parents_child(nd_parent, index, nd_right);
             self.fix_red_black_properties_after_delete(nd_right);
         } else if nd_parent == EMPTY_REF {
             self.root = EMPTY_REF;
@@ -525,15 +736,53 @@ impl<K: ExpiredKey<E>, E: Expiration, V: Copy> KeyExpTree<K, E, V> {
             // * node is black --> replace it by a temporary NIL node (needed to fix the R-B rules)
             if nd_color == Color::Black {
                 self.create_nil_node(nd_parent);
-                self.set_nil_parents_child(nd_parent, delete_index);
+                self.set_nil_parents_child(nd_parent, index);
                 self.fix_red_black_properties_after_delete(NIL_INDEX);
                 self.fix_parents_nil_child();
             } else {
-                self.remove_parents_child(nd_parent, delete_index);
+                self.remove_parents_child(nd_parent, index);
             }
         }
 
-        self.store.put_back(delete_index);
+        self.store.put_back(index);
+    }
+
+    // successor is the left minimum of the right subtree of index
+    fn swap_with_successor(&mut self, index: u32, successor: u32) {
+        let node = self.node(index);
+        let nd_parent = node.parent;
+        let nd_left = node.left;
+        let nd_right = node.right;
+        let nd_color = node.color;
+
+        let successor_node = self.node(successor);
+        let sc_parent = successor_node.parent;
+        let sc_right = successor_node.right;
+        let sc_color = successor_node.color;
+
+        self.replace_parents_child(nd_parent, index, successor);
+
+        self.node_mut(successor).left = nd_left;
+        self.node_mut(nd_left).parent = successor;
+
+        if sc_parent == index {
+            self.node_mut(successor).right = index;
+            self.node_mut(index).parent = successor;
+        } else {
+            self.node_mut(successor).right = nd_right;
+            self.node_mut(nd_right).parent = successor;
+            self.node_mut(sc_parent).left = index;
+            self.node_mut(index).parent = sc_parent;
+        }
+
+        self.node_mut(index).left = EMPTY_REF;
+        self.node_mut(index).right = sc_right;
+        if sc_right != EMPTY_REF {
+            self.node_mut(sc_right).parent = index;
+        }
+
+        self.node_mut(successor).color = nd_color;
+        self.node_mut(index).color = sc_color;
     }
 
     fn fix_red_black_properties_after_delete(&mut self, n_index: u32) {
@@ -1,6 +1,7 @@
 #![no_std]
 extern crate alloc;
 
+mod id_index;
 pub mod key;
 pub mod seg;
 pub mod set;
@@ -15,6 +16,10 @@ pub trait ExpiredVal<E: Expiration>: Copy {
     fn expiration(&self) -> E;
 }
 
+pub trait HasId {
+    fn id(&self) -> usize;
+}
+
 pub trait Expiration: Copy + Ord {
     fn max_expiration() -> Self;
 }
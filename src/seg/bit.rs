@@ -1,16 +1,172 @@
-pub(super) trait BitOp {
-    fn fill(start: u32, end: u32) -> u64;
+use crate::seg::sealed::Sealed;
+
+/// A bitset over the chunks of a heap, one bit per chunk.
+///
+/// The trait is sealed, it is implemented for `u64`, `u128` and `[u64; N]`.
+pub trait SegMask: Copy + Eq + Sealed {
+    const ZERO: Self;
+
+    /// Bits `start..=end` are set.
+    fn fill(start: u32, end: u32) -> Self;
+    fn from_words(words: &[u64]) -> Self;
+    fn and(self, other: Self) -> Self;
+    fn or(self, other: Self) -> Self;
+    fn is_zero(&self) -> bool;
+    /// Index of the lowest set bit, or the bit count if there is none.
+    fn first_bit(&self) -> u32;
+    fn clear_first_bit(&mut self);
 }
 
-impl BitOp for u64 {
+impl Sealed for u64 {}
+
+impl SegMask for u64 {
+    const ZERO: Self = 0;
+
     #[inline]
     fn fill(start: u32, end: u32) -> u64 {
-        ((1u64 << (end - start + 1)) - 1) << start
+        (u64::MAX >> (63 - (end - start))) << start
+    }
+
+    #[inline]
+    fn from_words(words: &[u64]) -> Self {
+        words[0]
+    }
+
+    #[inline]
+    fn and(self, other: Self) -> Self {
+        self & other
+    }
+
+    #[inline]
+    fn or(self, other: Self) -> Self {
+        self | other
+    }
+
+    #[inline]
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+
+    #[inline]
+    fn first_bit(&self) -> u32 {
+        self.trailing_zeros()
+    }
+
+    #[inline]
+    fn clear_first_bit(&mut self) {
+        *self &= *self - 1;
     }
 }
+
+impl Sealed for u128 {}
+
+impl SegMask for u128 {
+    const ZERO: Self = 0;
+
+    #[inline]
+    fn fill(start: u32, end: u32) -> u128 {
+        (u128::MAX >> (127 - (end - start))) << start
+    }
+
+    #[inline]
+    fn from_words(words: &[u64]) -> Self {
+        words[0] as u128 | (words[1] as u128) << 64
+    }
+
+    #[inline]
+    fn and(self, other: Self) -> Self {
+        self & other
+    }
+
+    #[inline]
+    fn or(self, other: Self) -> Self {
+        self | other
+    }
+
+    #[inline]
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+
+    #[inline]
+    fn first_bit(&self) -> u32 {
+        self.trailing_zeros()
+    }
+
+    #[inline]
+    fn clear_first_bit(&mut self) {
+        *self &= *self - 1;
+    }
+}
+
+impl<const N: usize> Sealed for [u64; N] {}
+
+impl<const N: usize> SegMask for [u64; N] {
+    const ZERO: Self = [0; N];
+
+    #[inline]
+    fn fill(start: u32, end: u32) -> Self {
+        let mut words = [0; N];
+        for (w, word) in words.iter_mut().enumerate() {
+            let w0 = 64 * w as u32;
+            let w1 = w0 + 63;
+            if end < w0 || w1 < start {
+                continue;
+            }
+            *word = u64::fill(start.max(w0) - w0, end.min(w1) - w0);
+        }
+        words
+    }
+
+    #[inline]
+    fn from_words(words: &[u64]) -> Self {
+        let mut result = [0; N];
+        result.copy_from_slice(&words[..N]);
+        result
+    }
+
+    #[inline]
+    fn and(mut self, other: Self) -> Self {
+        for (a, b) in self.iter_mut().zip(other) {
+            *a &= b;
+        }
+        self
+    }
+
+    #[inline]
+    fn or(mut self, other: Self) -> Self {
+        for (a, b) in self.iter_mut().zip(other) {
+            *a |= b;
+        }
+        self
+    }
+
+    #[inline]
+    fn is_zero(&self) -> bool {
+        self.iter().all(|&w| w == 0)
+    }
+
+    #[inline]
+    fn first_bit(&self) -> u32 {
+        for (w, &word) in self.iter().enumerate() {
+            if word != 0 {
+                return 64 * w as u32 + word.trailing_zeros();
+            }
+        }
+        64 * N as u32
+    }
+
+    #[inline]
+    fn clear_first_bit(&mut self) {
+        if let Some(word) = self.iter_mut().find(|w| **w != 0) {
+            *word &= *word - 1;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::seg::bit::BitOp;
+    use crate::seg::bit::SegMask;
 
     #[test]
     fn test_00() {
@@ -18,4 +174,19 @@ mod tests {
         assert_eq!(u64::fill(1, 2), 0b110);
         assert_eq!(u64::fill(2, 2), 0b100);
     }
+
+    #[test]
+    fn test_01() {
+        assert_eq!(u64::fill(0, 63), u64::MAX);
+        assert_eq!(u128::fill(60, 70), u128::fill(60, 63) | u128::fill(64, 70));
+        assert_eq!(<[u64; 3]>::fill(60, 130), [u64::fill(60, 63), u64::MAX, 0b111]);
+
+        let mut m = <[u64; 3]>::fill(100, 101);
+        assert_eq!(m.first_bit(), 100);
+        m.clear_first_bit();
+        assert_eq!(m.first_bit(), 101);
+        m.clear_first_bit();
+        assert!(m.is_zero());
+        assert_eq!(m.first_bit(), 192);
+    }
 }
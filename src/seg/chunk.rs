@@ -1,56 +1,142 @@
-use crate::seg::entity::Entity;
+use crate::seg::bit::SegMask;
+use crate::seg::entity::{Entity, Span, SpanId, Spans};
+use crate::seg::heap::SegHeap;
+use crate::seg::refine::SubHeap;
 use crate::{Expiration, ExpiredVal};
+use alloc::boxed::Box;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::mem;
 
-#[derive(Clone)]
-pub(super) struct Chunk<E, V> {
-    pub(super) buffer: Vec<Entity<E, V>>,
+pub(super) struct Chunk<E, V, H: SegHeap> {
+    pub(super) buffer: Vec<Entity<V, H::Mask>>,
+    // an overloaded leaf moves its values into a finer heap over its own span
+    pub(super) sub: Option<Box<SubHeap<E, V, H>>>,
+    // no value in the chunk expires before it
+    pub(super) min_exp: E,
 }
 
-impl<E: Expiration, V: ExpiredVal<E>> Chunk<E, V> {
+impl<E: Expiration, V: ExpiredVal<E>, H: SegHeap> Chunk<E, V, H> {
     #[inline]
     pub(super) fn new() -> Self {
-        Self { buffer: vec![] }
+        Self {
+            buffer: vec![],
+            sub: None,
+            min_exp: E::max_expiration(),
+        }
     }
 
     #[inline]
     pub(super) fn is_empty(&self) -> bool {
-        self.buffer.is_empty()
+        self.buffer.is_empty() && self.sub.is_none()
     }
 
     #[inline]
-    pub(super) fn entity(&self, index: usize) -> &Entity<E, V> {
+    pub(super) fn entity(&self, index: usize) -> &Entity<V, H::Mask> {
         unsafe { self.buffer.get_unchecked(index) }
     }
 
     #[inline]
-    pub(super) fn insert(&mut self, entity: Entity<E, V>) {
-        // self.clear_expired(time);
-        // self.min_exp = self.min_exp.min(entity.val.expiration());
-        self.buffer.push(entity);
-    }
-    //
-    // #[inline]
-    // pub(super) fn clear_expired(&mut self, time: E) {
-    //     if self.min_exp >= time {
-    //         return;
-    //     }
-    //     let mut new_min_exp = E::max_expiration();
-    //     self.buffer.retain(|entity| {
-    //         let exp = entity.val.expiration();
-    //         let keep = exp > time;
-    //         if keep {
-    //             new_min_exp = new_min_exp.min(exp);
-    //         }
-    //         keep
-    //     });
-    //     self.min_exp = new_min_exp;
-    // }
+    pub(super) fn insert(&mut self, entity: Entity<V, H::Mask>, span: Span) {
+        self.min_exp = self.min_exp.min(entity.val.expiration());
+        if let Some(sub) = &mut self.sub {
+            sub.insert(entity, span);
+        } else {
+            self.buffer.push(entity);
+        }
+    }
+
+    // the id of a live value placed with `mask` over `span` that matches `pred`
+    pub(super) fn find<P: Fn(&V) -> bool>(
+        &self,
+        mask: H::Mask,
+        span: Span,
+        pred: &P,
+        spans: &Spans,
+    ) -> Option<SpanId> {
+        let is_match =
+            |e: &Entity<V, H::Mask>| e.mask == mask && spans.get(e.id) == Some(span) && pred(&e.val);
+        if let Some(sub) = &self.sub {
+            return sub.find(span, is_match);
+        }
+        self.buffer.iter().find(|e| is_match(e)).map(|e| e.id)
+    }
+
+    // removes the copy of the value `id`, the copy can be missing
+    #[inline]
+    pub(super) fn remove(&mut self, id: SpanId, span: Span) -> Option<V> {
+        if let Some(sub) = &mut self.sub {
+            return sub.remove(id, span);
+        }
+        let pos = self.buffer.iter().position(|e| e.id == id)?;
+        Some(self.buffer.swap_remove(pos).val)
+    }
+
+    pub(super) fn retain<P: FnMut(&V) -> bool>(&mut self, pred: &mut P, index: usize, spans: &mut Spans) {
+        if let Some(sub) = &mut self.sub {
+            sub.retain(pred, index, spans);
+            return;
+        }
+        // `pred` sees a value at its first copy, the later copies follow its slot
+        self.buffer.retain(|e| {
+            let is_first = e.mask.first_bit() as usize == index;
+            let keep = spans.contains(e.id) && (!is_first || pred(&e.val));
+            if !keep && is_first {
+                spans.remove(e.id);
+            }
+            keep
+        });
+    }
+
+    pub(super) fn refine(&mut self, lo: i64, hi: i64, spans: &Spans) {
+        let entities = mem::take(&mut self.buffer);
+        self.sub = Some(Box::new(SubHeap::new(lo, hi, entities, spans)));
+    }
+
+    // returns the values to a flat buffer once the sub-heap is small again
+    pub(super) fn collapse_if_small(&mut self, limit: usize) {
+        if self.sub.as_ref().is_some_and(|sub| sub.len < limit / 2) {
+            self.buffer = self.sub.take().unwrap().into_entities();
+        }
+    }
+
+    #[inline]
+    pub(super) fn take_entities(&mut self) -> Vec<Entity<V, H::Mask>> {
+        self.min_exp = E::max_expiration();
+        match self.sub.take() {
+            Some(sub) => sub.into_entities(),
+            None => mem::take(&mut self.buffer),
+        }
+    }
+
+    // removes values that expire before `time`
+    pub(super) fn clear_expired(&mut self, time: E, index: usize, spans: &mut Spans) {
+        if self.min_exp >= time {
+            return;
+        }
+        if let Some(sub) = &mut self.sub {
+            sub.clear_expired(time, index, spans);
+            self.min_exp = sub.min_exp();
+            return;
+        }
+        let mut new_min_exp = E::max_expiration();
+        self.buffer.retain(|entity| {
+            let exp = entity.val.expiration();
+            let keep = exp >= time;
+            if keep {
+                new_min_exp = new_min_exp.min(exp);
+            } else if entity.mask.first_bit() as usize == index {
+                spans.remove(entity.id);
+            }
+            keep
+        });
+        self.min_exp = new_min_exp;
+    }
 
     #[inline]
     pub(super) fn clear(&mut self) {
-        // self.min_exp = E::max_expiration();
+        self.min_exp = E::max_expiration();
         self.buffer.clear();
+        self.sub = None;
     }
 }
@@ -0,0 +1,139 @@
+/// A coordinate that can be stored in a `SegExpTree`.
+///
+/// `to_ordered` must be monotone: `a <= b` implies `a.to_ordered() <= b.to_ordered()`.
+/// Values that map to the same integer fall into the same bucket.
+pub trait SegCoord: Copy {
+    fn to_ordered(self) -> i64;
+}
+
+macro_rules! impl_signed {
+    ($($t:ty),*) => {$(
+        impl SegCoord for $t {
+            #[inline]
+            fn to_ordered(self) -> i64 {
+                self as i64
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {$(
+        impl SegCoord for $t {
+            #[inline]
+            fn to_ordered(self) -> i64 {
+                // shift the unsigned range down so that 0 is i64::MIN
+                (self as u64 as i64) ^ i64::MIN
+            }
+        }
+    )*};
+}
+
+impl_signed!(i8, i16, i32, i64, isize);
+impl_unsigned!(u8, u16, u32, u64, usize);
+
+impl SegCoord for i128 {
+    #[inline]
+    fn to_ordered(self) -> i64 {
+        self.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+}
+
+impl SegCoord for u128 {
+    #[inline]
+    fn to_ordered(self) -> i64 {
+        (self.min(u64::MAX as u128) as u64).to_ordered()
+    }
+}
+
+/// The bits of a float are ordered as integers, so the buckets are logarithmic: narrow near
+/// zero and wide far from it. In a domain of `-5.0..7.0` almost all bit patterns lie between
+/// -1.0 and 1.0, so the values outside of it land in a few leaves. Use `Scaled` for buckets
+/// of equal width.
+impl SegCoord for f32 {
+    #[inline]
+    fn to_ordered(self) -> i64 {
+        // -0.0 becomes 0.0, negative floats have the sign bit set, flip the rest to reverse
+        // their order
+        let bits = (self + 0.0).to_bits() as i32;
+        (bits ^ (((bits >> 31) as u32) >> 1) as i32) as i64
+    }
+}
+
+/// Logarithmic buckets like `f32`.
+impl SegCoord for f64 {
+    #[inline]
+    fn to_ordered(self) -> i64 {
+        let bits = (self + 0.0).to_bits() as i64;
+        bits ^ (((bits >> 63) as u64) >> 1) as i64
+    }
+}
+
+/// A float coordinate quantized as `value * SCALE` truncated to an integer.
+///
+/// Unlike the bit-cast of a plain float, buckets have the same width over the whole domain.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Scaled<T, const SCALE: i64>(pub T);
+
+impl<const SCALE: i64> SegCoord for Scaled<f32, SCALE> {
+    #[inline]
+    fn to_ordered(self) -> i64 {
+        (self.0 as f64 * SCALE as f64) as i64
+    }
+}
+
+impl<const SCALE: i64> SegCoord for Scaled<f64, SCALE> {
+    #[inline]
+    fn to_ordered(self) -> i64 {
+        (self.0 * SCALE as f64) as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::seg::coord::{Scaled, SegCoord};
+
+    #[test]
+    fn test_00() {
+        assert!(0u64.to_ordered() < 1u64.to_ordered());
+        assert!((i64::MAX as u64).to_ordered() < (i64::MAX as u64 + 1).to_ordered());
+        assert_eq!(0u64.to_ordered(), i64::MIN);
+        assert_eq!(u64::MAX.to_ordered(), i64::MAX);
+        assert_eq!(u128::MAX.to_ordered(), i64::MAX);
+        assert_eq!(i128::MIN.to_ordered(), i64::MIN);
+        assert_eq!((-5i128).to_ordered(), -5);
+    }
+
+    #[test]
+    fn test_01() {
+        let values = [
+            f64::NEG_INFINITY,
+            -1e300,
+            -2.5,
+            -1.0,
+            -1e-300,
+            -0.0,
+            0.0,
+            1e-300,
+            1.0,
+            2.5,
+            1e300,
+            f64::INFINITY,
+        ];
+        for w in values.windows(2) {
+            assert!(w[0].to_ordered() <= w[1].to_ordered());
+            assert!((w[0] as f32).to_ordered() <= (w[1] as f32).to_ordered());
+        }
+        // both zeros fall into the same bucket
+        assert_eq!((-0.0f64).to_ordered(), 0.0f64.to_ordered());
+        assert_eq!((-0.0f32).to_ordered(), 0.0f32.to_ordered());
+        assert_eq!(0.0f64.to_ordered(), 0);
+        assert!((-1e-300f64).to_ordered() < 0);
+    }
+
+    #[test]
+    fn test_02() {
+        assert_eq!(Scaled::<f64, 100>(1.25).to_ordered(), 125);
+        assert_eq!(Scaled::<f32, 100>(-0.5).to_ordered(), -50);
+    }
+}
@@ -1,20 +1,150 @@
-use crate::{Expiration, ExpiredVal};
-use core::marker::PhantomData;
+use alloc::vec::Vec;
 
+/// One copy of a value, there is one in every chunk of `mask`.
 #[derive(Clone, Copy)]
-pub(super) struct Entity<E, V> {
+pub(super) struct Entity<V, M> {
     pub(super) val: V,
-    pub(super) mask: u64,
-    phantom_data: PhantomData<E>,
+    pub(super) mask: M,
+    // all copies of a value share the id, its range is stored once in `Spans`
+    pub(super) id: SpanId,
 }
 
-impl<E: Expiration, V: ExpiredVal<E>> Entity<E, V> {
+/// A copy inside a refined chunk.
+#[derive(Clone, Copy)]
+pub(super) struct SubEntity<V, M> {
+    pub(super) entity: Entity<V, M>,
+    // the place mask inside the sub-heap
+    pub(super) sub_mask: M,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Span {
+    pub(super) min: i64,
+    pub(super) max: i64,
+}
+
+impl Span {
+    // stands in for the range where a query does not compare it
+    pub(super) const FULL: Span = Span {
+        min: i64::MIN,
+        max: i64::MAX,
+    };
+
     #[inline]
-    pub(super) fn new(val: V, mask: u64) -> Self {
+    pub(super) fn is_overlap(&self, min: i64, max: i64) -> bool {
+        self.min <= max && min <= self.max
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct SpanId {
+    index: u32,
+    generation: u32,
+}
+
+struct Slot {
+    span: Span,
+    // odd while the slot is in use
+    generation: u32,
+}
+
+/// The ranges of the stored values, one slot per value.
+///
+/// A slot is freed when the first copy of its value is dropped. Copies left in other chunks
+/// keep the old generation and are dropped as dead when they are met.
+pub(super) struct Spans {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+impl Spans {
+    #[inline]
+    pub(super) fn new() -> Self {
         Self {
-            val,
-            mask,
-            phantom_data: Default::default(),
+            slots: Vec::new(),
+            free: Vec::new(),
         }
     }
+
+    #[inline]
+    pub(super) fn insert(&mut self, span: Span) -> SpanId {
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.span = span;
+            slot.generation = slot.generation.wrapping_add(1);
+            SpanId {
+                index,
+                generation: slot.generation,
+            }
+        } else {
+            let index = self.slots.len() as u32;
+            self.slots.push(Slot { span, generation: 1 });
+            SpanId { index, generation: 1 }
+        }
+    }
+
+    #[inline]
+    pub(super) fn get(&self, id: SpanId) -> Option<Span> {
+        let slot = self.slots.get(id.index as usize)?;
+        if slot.generation == id.generation {
+            Some(slot.span)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub(super) fn contains(&self, id: SpanId) -> bool {
+        self.get(id).is_some()
+    }
+
+    // a stale id is ignored
+    #[inline]
+    pub(super) fn remove(&mut self, id: SpanId) {
+        let Some(slot) = self.slots.get_mut(id.index as usize) else {
+            return;
+        };
+        if slot.generation == id.generation {
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push(id.index);
+        }
+    }
+
+    #[inline]
+    pub(super) fn clear(&mut self) {
+        self.free.clear();
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.generation & 1 == 1 {
+                slot.generation = slot.generation.wrapping_add(1);
+            }
+            self.free.push(index as u32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::seg::entity::{Span, Spans};
+
+    #[test]
+    fn test_00() {
+        let mut spans = Spans::new();
+        let a = spans.insert(Span { min: 0, max: 5 });
+        let b = spans.insert(Span { min: 3, max: 3 });
+        assert_eq!(spans.get(a), Some(Span { min: 0, max: 5 }));
+
+        spans.remove(a);
+        assert!(!spans.contains(a));
+        // the slot is reused, the old id stays dead
+        let c = spans.insert(Span { min: 7, max: 9 });
+        assert!(!spans.contains(a));
+        spans.remove(a);
+        assert_eq!(spans.get(c), Some(Span { min: 7, max: 9 }));
+
+        spans.clear();
+        assert!(!spans.contains(b) && !spans.contains(c));
+        let d = spans.insert(Span { min: 1, max: 2 });
+        assert!(!spans.contains(b) && !spans.contains(c));
+        assert!(spans.contains(d));
+    }
 }
@@ -1,9 +1,55 @@
+use core::fmt;
+
 #[derive(Debug, Clone, Copy)]
 pub struct SegRange<R> {
     pub min: R,
     pub max: R,
 }
 
+/// How `iter_by_range` filters the stored values.
+///
+/// Both modes keep the range of every value once, two `i64` and a generation in a table next
+/// to the chunks. Removal, point queries, refined leaves and the `Grow` policy need it. A
+/// candidate range query does not read the table: a removed value leaves all chunks at once,
+/// so the copies it meets are either live or expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegMode {
+    /// Yields every value whose bucket overlaps the query, the caller does the exact test.
+    Candidate,
+    /// Yields only values whose range really intersects the query.
+    Exact,
+}
+
+/// What happens to a range that does not fit into the domain of the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegPolicy {
+    /// The range is clamped to the domain. Its original bounds are kept for exact tests.
+    Clamp,
+    /// The range is refused with `SegError::OutOfDomain`.
+    Reject,
+    /// The domain is doubled until the range fits and all values are moved to the new layout.
+    Grow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegError {
+    /// `min` is greater than `max`.
+    InvalidRange,
+    /// The range is outside the domain and the policy does not allow it.
+    OutOfDomain,
+}
+
+impl fmt::Display for SegError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegError::InvalidRange => f.write_str("range min is greater than max"),
+            SegError::OutOfDomain => f.write_str("range is outside the domain"),
+        }
+    }
+}
+
+impl core::error::Error for SegError {}
+
 pub trait SegExpCollection<R, E, V> {
     type Iter<'a>: Iterator<Item = V>
     where
@@ -12,12 +58,66 @@ pub trait SegExpCollection<R, E, V> {
     fn insert_by_range(&mut self, range: SegRange<R>, val: V);
     fn iter_by_range(&mut self, range: SegRange<R>, time: E) -> Self::Iter<'_>;
 
+    /// Iterates over the values at `point`. The default queries the range `point..=point`.
+    fn iter_at_point(&mut self, point: R, time: E) -> Self::Iter<'_>
+    where
+        R: Clone,
+    {
+        let range = SegRange {
+            min: point.clone(),
+            max: point,
+        };
+        self.iter_by_range(range, time)
+    }
+
     fn clear(&mut self);
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::seg::exp::{SegExpCollection, SegRange};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    // a plain list that relies on the default `iter_at_point`
+    struct List {
+        values: Vec<(SegRange<i32>, i32)>,
+    }
+
+    impl SegExpCollection<i32, i32, i32> for List {
+        type Iter<'a> = vec::IntoIter<i32>;
+
+        fn insert_by_range(&mut self, range: SegRange<i32>, val: i32) {
+            self.values.push((range, val));
+        }
+
+        fn iter_by_range(&mut self, range: SegRange<i32>, time: i32) -> Self::Iter<'_> {
+            self.values.retain(|&(_, val)| val >= time);
+            let result: Vec<i32> = self
+                .values
+                .iter()
+                .filter(|(r, _)| r.min <= range.max && range.min <= r.max)
+                .map(|&(_, val)| val)
+                .collect();
+            result.into_iter()
+        }
+
+        fn clear(&mut self) {
+            self.values.clear();
+        }
+    }
 
     #[test]
     fn test_00() {}
+
+    #[test]
+    fn test_01() {
+        let mut list = List { values: Vec::new() };
+        list.insert_by_range(SegRange { min: 0, max: 10 }, 5);
+        list.insert_by_range(SegRange { min: 5, max: 20 }, 30);
+        assert_eq!(list.iter_at_point(3, 0).collect::<Vec<_>>(), vec![5]);
+        assert_eq!(list.iter_at_point(7, 0).count(), 2);
+        assert_eq!(list.iter_at_point(7, 10).collect::<Vec<_>>(), vec![30]);
+        assert_eq!(list.iter_at_point(25, 0).count(), 0);
+    }
 }
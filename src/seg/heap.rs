@@ -1,116 +1,168 @@
-use crate::seg::bit::BitOp;
-
-pub(super) struct Heap32 {}
+use crate::seg::bit::SegMask;
+use crate::seg::sealed::Sealed;
+
+/// The shape of the chunk heap: `2^POWER` leaves and `2^(POWER + 1) - 1` chunks.
+///
+/// A deeper heap splits the domain into narrower leaves, so queries return fewer false
+/// candidates, at the cost of more chunks and wider masks.
+///
+/// The trait is sealed, use one of `Heap32`, `Heap64`, `Heap128` and `Heap256`.
+pub trait SegHeap: Sealed {
+    type Mask: SegMask;
+    const POWER: u32;
+
+    #[doc(hidden)]
+    fn point_to_chain_mask(order: u32) -> Self::Mask;
+}
 
-impl Heap32 {
-    pub(super) const SUB_CAPACITY: u32 = 32 - 1;
-    pub(super) const POWER: u32 = 32_u32.ilog2();
+pub(super) trait HeapOp: SegHeap {
+    const LEAVES: u32 = 1 << Self::POWER;
+    const SUB_CAPACITY: u32 = Self::LEAVES - 1;
 
+    // all nodes that overlap start..=end, level by level
     #[inline]
-    pub(super) fn range_to_intersect_mask(start: u32, end: u32) -> u64 {
-        debug_assert!(start < 32);
-        debug_assert!(end < 32);
-
-        let mut w = Self::range_to_fill_mask(start, end);
-
-        let mut shift = 32;
-        for _ in 0..6 {
-            let mut lt = shift - 1;
-            shift >>= 1; // 16
-            for _ in 0..shift {
-                let rt = lt + 1;
-                let pt = lt >> 1;
-
-                let lt_bit = (w >> lt) & 1;
-                let rt_bit = (w >> rt) & 1;
-                let pt_bit = lt_bit | rt_bit;
-
-                w |= pt_bit << pt;
-
-                lt += 2;
+    fn range_to_intersect_mask(start: u32, end: u32) -> Self::Mask {
+        debug_assert!(start <= end && end < Self::LEAVES);
+
+        let mut l = Self::order_to_heap_index(start);
+        let mut r = Self::order_to_heap_index(end);
+        let mut m = Self::Mask::ZERO;
+        loop {
+            m = m.or(Self::Mask::fill(l, r));
+            if l == 0 {
+                return m;
             }
+            l = (l - 1) >> 1;
+            r = (r - 1) >> 1;
         }
+    }
 
-        w
+    // the smallest set of nodes that covers start..=end
+    #[inline]
+    fn range_to_place_mask(start: u32, end: u32) -> Self::Mask {
+        debug_assert!(start <= end && end < Self::LEAVES);
+
+        let mut l = Self::order_to_heap_index(start);
+        let mut r = Self::order_to_heap_index(end);
+        let mut m = Self::Mask::ZERO;
+        loop {
+            if l == r {
+                return m.or(Self::Mask::fill(l, l));
+            }
+            // a right child at the left end and a left child at the right end stay
+            if l & 1 == 0 {
+                m = m.or(Self::Mask::fill(l, l));
+                l += 1;
+            }
+            if r & 1 == 1 {
+                m = m.or(Self::Mask::fill(r, r));
+                r -= 1;
+            }
+            if l > r {
+                return m;
+            }
+            l = (l - 1) >> 1;
+            r = (r - 1) >> 1;
+        }
     }
 
     #[inline]
-    pub(super) fn range_to_place_mask(start: u32, end: u32) -> u64 {
-        debug_assert!(start < 32);
-        debug_assert!(end < 32);
+    fn order_to_heap_index(order: u32) -> u32 {
+        order + Self::SUB_CAPACITY
+    }
+}
 
-        if end - start == 31 {
-            return 1;
+impl<H: SegHeap> HeapOp for H {}
+
+// root-to-leaf chain for every leaf order, as W words per mask
+const fn chain_words<const W: usize, const L: usize>() -> [[u64; W]; L] {
+    let mut masks = [[0; W]; L];
+    let mut order = 0;
+    while order < L {
+        let mut i = order + L - 1;
+        loop {
+            masks[order][i >> 6] |= 1 << (i & 63);
+            if i == 0 {
+                break;
+            }
+            i = (i - 1) >> 1;
         }
+        order += 1;
+    }
+    masks
+}
 
-        let mut w = Self::range_to_fill_mask(start, end);
+macro_rules! heap {
+    ($(#[$doc:meta])* $name:ident, $power:expr, $mask:ty, $words:expr) => {
+        $(#[$doc])*
+        pub struct $name;
 
-        let mut m: u64 = 0;
-        let mut shift = 32;
-        for _ in 0..6 {
-            let mut lt = shift - 1;
-            shift >>= 1; // 16
+        // every chunk needs a bit of the mask
+        const _: () = assert!((2 << $power) - 1 <= 64 * $words);
 
-            for _ in 0..shift {
-                let rt = lt + 1;
-                let pt = lt >> 1;
+        impl Sealed for $name {}
 
-                let lt_bit = (w >> lt) & 1;
-                let rt_bit = (w >> rt) & 1;
-                let pt_bit = lt_bit & rt_bit;
+        impl $name {
+            const CHAIN_MASKS: [[u64; $words]; 1 << $power] = chain_words::<$words, { 1 << $power }>();
+        }
 
-                w |= pt_bit << pt;
-                m |= (lt_bit ^ pt_bit) << lt;
-                m |= (rt_bit ^ pt_bit) << rt;
+        impl SegHeap for $name {
+            type Mask = $mask;
+            const POWER: u32 = $power;
 
-                lt += 2;
+            #[inline]
+            fn point_to_chain_mask(order: u32) -> $mask {
+                <$mask>::from_words(&Self::CHAIN_MASKS[order as usize])
             }
         }
-
-        m
-    }
-
-    #[inline]
-    fn range_to_fill_mask(start: u32, end: u32) -> u64 {
-        let i0 = Self::order_to_heap_index(start);
-        let i1 = Self::order_to_heap_index(end);
-        u64::fill(i0, i1)
-    }
-
-    #[inline]
-    pub(super) fn order_to_heap_index(order: u32) -> u32 {
-        order + Self::SUB_CAPACITY
-    }
+    };
 }
 
-pub(super) struct BitIter {
-    value: u64,
+heap!(
+    /// 32 leaves, 63 chunks.
+    Heap32, 5, u64, 1
+);
+heap!(
+    /// 64 leaves, 127 chunks.
+    Heap64, 6, u128, 2
+);
+heap!(
+    /// 128 leaves, 255 chunks.
+    Heap128, 7, [u64; 4], 4
+);
+heap!(
+    /// 256 leaves, 511 chunks.
+    Heap256, 8, [u64; 8], 8
+);
+
+pub(super) struct BitIter<M> {
+    value: M,
 }
 
-impl BitIter {
+impl<M: SegMask> BitIter<M> {
     #[inline]
-    pub(super) fn new(value: u64) -> Self {
+    pub(super) fn new(value: M) -> Self {
         Self { value }
     }
 }
 
-impl Iterator for BitIter {
+impl<M: SegMask> Iterator for BitIter<M> {
     type Item = usize;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        if self.value == 0 {
+        if self.value.is_zero() {
             return None;
         }
-        let pos = self.value.trailing_zeros() as usize;
-        self.value &= self.value - 1;
+        let pos = self.value.first_bit() as usize;
+        self.value.clear_first_bit();
         Some(pos)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::seg::heap::{BitIter, Heap32};
+    use crate::seg::heap::{BitIter, Heap32, Heap64, Heap128, Heap256, HeapOp, SegHeap};
     use alloc::vec;
     use alloc::vec::Vec;
 
@@ -191,4 +243,56 @@ mod tests {
         let template: Vec<_> = (0..63).collect();
         assert_eq!(indices, template);
     }
+
+    #[test]
+    fn test_10() {
+        for order in 0..32 {
+            assert_eq!(
+                Heap32::point_to_chain_mask(order),
+                Heap32::range_to_intersect_mask(order, order)
+            );
+        }
+    }
+
+    fn check_heap<H: SegHeap>() {
+        let leaves = H::LEAVES;
+        let nodes = 2 * leaves - 1;
+        // leaves under a node
+        let cover = |mut i: u32| {
+            let mut depth = 0;
+            while i < H::SUB_CAPACITY {
+                i = 2 * i + 1;
+                depth += 1;
+            }
+            let start = i - H::SUB_CAPACITY;
+            start..start + (1 << depth)
+        };
+        for start in 0..leaves {
+            assert!(H::point_to_chain_mask(start) == H::range_to_intersect_mask(start, start));
+            for end in (start..leaves).step_by(3) {
+                let place: Vec<_> = BitIter::new(H::range_to_place_mask(start, end)).collect();
+                let mut covered: Vec<_> = place.iter().flat_map(|&i| cover(i as u32)).collect();
+                covered.sort_unstable();
+                assert_eq!(covered, (start..=end).collect::<Vec<_>>());
+
+                let inter: Vec<_> = BitIter::new(H::range_to_intersect_mask(start, end)).collect();
+                let template: Vec<_> = (0..nodes)
+                    .filter(|&i| {
+                        let c = cover(i);
+                        c.start <= end && start < c.end
+                    })
+                    .map(|i| i as usize)
+                    .collect();
+                assert_eq!(inter, template);
+            }
+        }
+    }
+
+    #[test]
+    fn test_11() {
+        check_heap::<Heap32>();
+        check_heap::<Heap64>();
+        check_heap::<Heap128>();
+        check_heap::<Heap256>();
+    }
 }
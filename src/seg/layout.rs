@@ -1,60 +1,152 @@
-use crate::seg::heap::Heap32;
+use crate::seg::heap::{HeapOp, SegHeap};
+use alloc::vec::Vec;
+use core::marker::PhantomData;
 
-pub(super) struct Layout {
+pub(super) struct Layout<H> {
     min: i64,
     max: i64,
     scale: u32,
+    // leaf starts of a compressed layout, empty for a uniform one
+    starts: Vec<i64>,
+    phantom_data: PhantomData<H>,
 }
 
-impl Layout {
+impl<H: SegHeap> Layout<H> {
     #[inline]
     pub(super) fn new(start: i64, end: i64) -> Option<Self> {
+        if start > end {
+            return None;
+        }
         let min = start;
         let max = end;
-        let len = (max - min + 1) as usize;
-        if len < Heap32::POWER as usize {
-            return None;
+        // the width can exceed i64::MAX
+        let diff = max.wrapping_sub(min) as u64;
+        let p = if diff == 0 { 0 } else { diff.ilog2() + 1 };
+        let scale = p.saturating_sub(H::POWER);
+
+        Some(Self {
+            min,
+            max,
+            scale,
+            starts: Vec::new(),
+            phantom_data: PhantomData,
+        })
+    }
+
+    // every leaf gets the same number of breakpoints, `points` must be sorted and distinct
+    pub(super) fn with_breakpoints(points: &[i64]) -> Option<Self> {
+        let (&min, &max) = (points.first()?, points.last()?);
+        let n = points.len();
+        let leaves = H::LEAVES as usize;
+        let starts = if n <= leaves {
+            points.to_vec()
+        } else {
+            (0..leaves).map(|k| points[k * n / leaves]).collect()
+        };
+
+        Some(Self {
+            min,
+            max,
+            scale: 0,
+            starts,
+            phantom_data: PhantomData,
+        })
+    }
+
+    // doubles the domain towards min and max until both fit
+    pub(super) fn grow(&self, min: i64, max: i64) -> Self {
+        let mut lo = self.min as i128;
+        let mut hi = self.max as i128;
+        while (min as i128) < lo || (max as i128) > hi {
+            let width = hi - lo + 1;
+            if (min as i128) < lo {
+                lo = (lo - width).max(i64::MIN as i128);
+            }
+            if (max as i128) > hi {
+                hi = (hi + width).min(i64::MAX as i128);
+            }
         }
-        let p = (len - 1).ilog2() + 1;
-        if p < Heap32::POWER {
-            return None;
+        let scale = Self::new(lo as i64, hi as i64).map_or(self.scale, |l| l.scale);
+        Self {
+            min: lo as i64,
+            max: hi as i64,
+            scale,
+            starts: Vec::new(),
+            phantom_data: PhantomData,
         }
-        let scale = p - Heap32::POWER;
+    }
 
-        Some(Self { min, max, scale })
+    #[inline]
+    pub(super) fn contains(&self, min: i64, max: i64) -> bool {
+        self.min <= min && max <= self.max
+    }
+
+    #[inline]
+    pub(super) fn clamp(&self, value: i64) -> i64 {
+        value.clamp(self.min, self.max)
     }
 
     #[inline]
     pub(super) fn index(&self, value: i64) -> u32 {
-        ((value - self.min) >> self.scale) as u32
+        debug_assert!(self.min <= value && value <= self.max);
+        if self.starts.is_empty() {
+            (value.wrapping_sub(self.min) as u64 >> self.scale) as u32
+        } else {
+            (self.starts.partition_point(|&s| s <= value) - 1) as u32
+        }
+    }
+
+    // the coordinates of a leaf chunk
+    #[inline]
+    pub(super) fn leaf_span(&self, order: u32) -> (i64, i64) {
+        if !self.starts.is_empty() {
+            let i = order as usize;
+            let hi = self.starts.get(i + 1).map_or(self.max, |&s| s - 1);
+            return (self.starts[i], hi);
+        }
+        let width = self.max.wrapping_sub(self.min) as u64 as u128;
+        let lo = (order as u128) << self.scale;
+        let hi = (((order as u128 + 1) << self.scale) - 1).min(width);
+        (
+            self.min.wrapping_add(lo as u64 as i64),
+            self.min.wrapping_add(hi as u64 as i64),
+        )
     }
 
     #[inline]
     pub(super) fn count(&self) -> usize {
         let order = self.index(self.max);
-        Heap32::order_to_heap_index(order) as usize + 1
+        H::order_to_heap_index(order) as usize + 1
     }
 
     #[inline]
-    pub(super) fn insert_mask(&self, min: i64, max: i64) -> u64 {
+    pub(super) fn insert_mask(&self, min: i64, max: i64) -> H::Mask {
         let start = self.index(min);
         let end = self.index(max);
 
-        Heap32::range_to_place_mask(start, end)
+        H::range_to_place_mask(start, end)
     }
 
     #[inline]
-    pub(super) fn intersect_mask(&self, min: i64, max: i64) -> u64 {
+    pub(super) fn intersect_mask(&self, min: i64, max: i64) -> H::Mask {
         let start = self.index(min);
         let end = self.index(max);
 
-        Heap32::range_to_intersect_mask(start, end)
+        H::range_to_intersect_mask(start, end)
+    }
+
+    #[inline]
+    pub(super) fn point_mask(&self, value: i64) -> H::Mask {
+        H::point_to_chain_mask(self.index(value))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::seg::layout::Layout;
+    use crate::seg::heap::Heap32;
+    use alloc::vec::Vec;
+
+    type Layout = crate::seg::layout::Layout<Heap32>;
 
     #[test]
     fn test_00() {
@@ -89,4 +181,72 @@ mod tests {
 
         assert_ne!(inter, 0);
     }
+
+    #[test]
+    fn test_04() {
+        let layout = Layout::new(5, 9).unwrap();
+        for i in 5..=9 {
+            assert_eq!(layout.index(i), (i - 5) as u32);
+        }
+        assert_eq!(layout.count(), 36);
+
+        let layout = Layout::new(7, 7).unwrap();
+        assert_eq!(layout.index(7), 0);
+        assert!(Layout::new(8, 7).is_none());
+    }
+
+    #[test]
+    fn test_05() {
+        let layout = Layout::new(i64::MIN, i64::MAX).unwrap();
+        assert_eq!(layout.index(i64::MIN), 0);
+        assert_eq!(layout.index(-1), 15);
+        assert_eq!(layout.index(0), 16);
+        assert_eq!(layout.index(i64::MAX), 31);
+    }
+
+    #[test]
+    fn test_06() {
+        let layout = Layout::new(0, 31).unwrap();
+        let grown = layout.grow(0, 40);
+        assert_eq!((grown.min, grown.max, grown.scale), (0, 63, 1));
+        let grown = layout.grow(-100, 10);
+        assert_eq!((grown.min, grown.max, grown.scale), (-224, 31, 3));
+        let grown = layout.grow(i64::MIN, i64::MAX);
+        assert_eq!((grown.min, grown.max), (i64::MIN, i64::MAX));
+    }
+
+    #[test]
+    fn test_07() {
+        let layout = Layout::new(-100, 100).unwrap();
+        assert_eq!(layout.leaf_span(0), (-100, -93));
+        assert_eq!(layout.leaf_span(25), (100, 100));
+
+        let layout = Layout::new(i64::MIN, i64::MAX).unwrap();
+        assert_eq!(layout.leaf_span(0).0, i64::MIN);
+        assert_eq!(layout.leaf_span(31).1, i64::MAX);
+        assert_eq!(layout.leaf_span(15).1 + 1, layout.leaf_span(16).0);
+    }
+
+    #[test]
+    fn test_08() {
+        let points: Vec<i64> = (0..96).map(|i| i * i).collect();
+        let layout = Layout::with_breakpoints(&points).unwrap();
+        // three breakpoints per leaf
+        for (i, &p) in points.iter().enumerate() {
+            assert_eq!(layout.index(p), (i / 3) as u32);
+            if i + 1 < points.len() {
+                assert_eq!(layout.index(p + 1), (i / 3) as u32);
+            }
+        }
+        assert_eq!(layout.leaf_span(1), (9, 35));
+        assert_eq!(layout.leaf_span(31), (93 * 93, 95 * 95));
+        assert_eq!(layout.count(), 63);
+
+        let layout = Layout::with_breakpoints(&[-5, 0, 7]).unwrap();
+        assert_eq!(layout.index(-1), 0);
+        assert_eq!(layout.index(3), 1);
+        assert_eq!(layout.index(7), 2);
+        assert_eq!(layout.count(), 34);
+        assert!(Layout::with_breakpoints(&[]).is_none());
+    }
 }
@@ -1,7 +1,14 @@
-mod bit;
+pub mod bit;
 mod chunk;
+pub mod coord;
 mod entity;
 pub mod exp;
-mod heap;
+pub mod heap;
 mod layout;
+mod refine;
 pub mod tree;
+
+mod sealed {
+    // keeps `SegHeap` and `SegMask` to the shapes of this crate, whose masks fit their heaps
+    pub trait Sealed {}
+}
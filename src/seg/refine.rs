@@ -0,0 +1,172 @@
+use crate::seg::bit::SegMask;
+use crate::seg::entity::{Entity, Span, SpanId, Spans, SubEntity};
+use crate::seg::heap::{BitIter, SegHeap};
+use crate::seg::layout::Layout;
+use crate::{Expiration, ExpiredVal};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A heap nested in one leaf chunk. Its chunks never refine again.
+pub(super) struct SubHeap<E, V, H: SegHeap> {
+    pub(super) layout: Layout<H>,
+    pub(super) chunks: Vec<SubChunk<E, V, H::Mask>>,
+    // values, not copies, it can only be too high while expired values wait for removal
+    pub(super) len: usize,
+}
+
+pub(super) struct SubChunk<E, V, M> {
+    pub(super) buffer: Vec<SubEntity<V, M>>,
+    // no value in the chunk expires before it
+    pub(super) min_exp: E,
+}
+
+impl<E: Expiration, V: ExpiredVal<E>, M: SegMask> SubChunk<E, V, M> {
+    #[inline]
+    fn new() -> Self {
+        Self {
+            buffer: vec![],
+            min_exp: E::max_expiration(),
+        }
+    }
+
+    // removes values that expire before `time`, returns how many were in their first chunk,
+    // `outer` is the position of the refined chunk in the outer heap
+    fn clear_expired(&mut self, time: E, index: usize, outer: usize, spans: &mut Spans) -> usize {
+        if self.min_exp >= time {
+            return 0;
+        }
+        let mut new_min_exp = E::max_expiration();
+        let mut count = 0;
+        self.buffer.retain(|e| {
+            let exp = e.entity.val.expiration();
+            let keep = exp >= time;
+            if keep {
+                new_min_exp = new_min_exp.min(exp);
+            } else if e.sub_mask.first_bit() as usize == index {
+                count += 1;
+                if e.entity.mask.first_bit() as usize == outer {
+                    spans.remove(e.entity.id);
+                }
+            }
+            keep
+        });
+        self.min_exp = new_min_exp;
+        count
+    }
+}
+
+impl<E: Expiration, V: ExpiredVal<E>, H: SegHeap> SubHeap<E, V, H> {
+    // dead copies among `entities` are dropped
+    pub(super) fn new(lo: i64, hi: i64, entities: Vec<Entity<V, H::Mask>>, spans: &Spans) -> Self {
+        let layout = Layout::new(lo, hi).unwrap();
+        let chunks = (0..layout.count()).map(|_| SubChunk::new()).collect();
+        let mut sub = Self {
+            layout,
+            chunks,
+            len: 0,
+        };
+        for entity in entities {
+            if let Some(span) = spans.get(entity.id) {
+                sub.insert(entity, span);
+            }
+        }
+        sub
+    }
+
+    #[inline]
+    fn place_mask(&self, span: Span) -> H::Mask {
+        self.layout
+            .insert_mask(self.layout.clamp(span.min), self.layout.clamp(span.max))
+    }
+
+    #[inline]
+    pub(super) fn insert(&mut self, entity: Entity<V, H::Mask>, span: Span) {
+        let sub_mask = self.place_mask(span);
+        let exp = entity.val.expiration();
+        for index in BitIter::new(sub_mask) {
+            let chunk = &mut self.chunks[index];
+            chunk.min_exp = chunk.min_exp.min(exp);
+            chunk.buffer.push(SubEntity { entity, sub_mask });
+        }
+        self.len += 1;
+    }
+
+    pub(super) fn find<F: Fn(&Entity<V, H::Mask>) -> bool>(&self, span: Span, is_match: F) -> Option<SpanId> {
+        BitIter::new(self.place_mask(span)).find_map(|index| {
+            self.chunks[index]
+                .buffer
+                .iter()
+                .find(|e| is_match(&e.entity))
+                .map(|e| e.entity.id)
+        })
+    }
+
+    // removes every copy of the value `id`, some of them can be missing
+    pub(super) fn remove(&mut self, id: SpanId, span: Span) -> Option<V> {
+        let mut result = None;
+        for index in BitIter::new(self.place_mask(span)) {
+            let buffer = &mut self.chunks[index].buffer;
+            let Some(pos) = buffer.iter().position(|e| e.entity.id == id) else {
+                continue;
+            };
+            let e = buffer.swap_remove(pos);
+            if e.sub_mask.first_bit() as usize == index {
+                self.len -= 1;
+            }
+            result = Some(e.entity.val);
+        }
+        result
+    }
+
+    // `index` is the position of the refined chunk in the outer heap
+    pub(super) fn retain<P: FnMut(&V) -> bool>(&mut self, pred: &mut P, index: usize, spans: &mut Spans) {
+        let mut len = 0;
+        for (j, chunk) in self.chunks.iter_mut().enumerate() {
+            chunk.buffer.retain(|e| {
+                let is_first =
+                    e.entity.mask.first_bit() as usize == index && e.sub_mask.first_bit() as usize == j;
+                let keep = spans.contains(e.entity.id) && (!is_first || pred(&e.entity.val));
+                if !keep && is_first {
+                    spans.remove(e.entity.id);
+                }
+                keep
+            });
+            len += chunk
+                .buffer
+                .iter()
+                .filter(|e| e.sub_mask.first_bit() as usize == j)
+                .count();
+        }
+        self.len = len;
+    }
+
+    pub(super) fn clear_expired(&mut self, time: E, index: usize, spans: &mut Spans) {
+        for (j, chunk) in self.chunks.iter_mut().enumerate() {
+            self.len -= chunk.clear_expired(time, j, index, spans);
+        }
+    }
+
+    #[inline]
+    pub(super) fn min_exp(&self) -> E {
+        self.chunks
+            .iter()
+            .map(|c| c.min_exp)
+            .min()
+            .unwrap_or(E::max_expiration())
+    }
+
+    // every value once, taken from the first chunk of its sub mask
+    pub(super) fn into_entities(self) -> Vec<Entity<V, H::Mask>> {
+        let mut result = Vec::with_capacity(self.len);
+        for (j, chunk) in self.chunks.into_iter().enumerate() {
+            result.extend(
+                chunk
+                    .buffer
+                    .into_iter()
+                    .filter(|e| e.sub_mask.first_bit() as usize == j)
+                    .map(|e| e.entity),
+            );
+        }
+        result
+    }
+}
@@ -1,72 +1,311 @@
+use crate::seg::bit::SegMask;
 use crate::seg::chunk::Chunk;
-use crate::seg::entity::Entity;
-use crate::seg::exp::{SegExpCollection, SegRange};
-use crate::seg::heap::BitIter;
+use crate::seg::coord::SegCoord;
+use crate::seg::entity::{Entity, Span, Spans, SubEntity};
+use crate::seg::exp::{SegError, SegExpCollection, SegMode, SegPolicy, SegRange};
+use crate::seg::heap::{BitIter, Heap32, HeapOp, SegHeap};
 use crate::seg::layout::Layout;
 use crate::{Expiration, ExpiredVal};
-use alloc::vec;
 use alloc::vec::Vec;
 use core::marker::PhantomData;
 
-pub struct SegExpTree<R, E, V> {
-    layout: Layout,
-    chunks: Vec<Chunk<E, V>>,
+pub struct SegExpTree<R, E, V, H: SegHeap = Heap32> {
+    layout: Layout<H>,
+    chunks: Vec<Chunk<E, V, H>>,
+    spans: Spans,
+    mode: SegMode,
+    policy: SegPolicy,
+    refine_limit: usize,
     phantom_data: PhantomData<R>,
 }
 
 impl<R, E: Expiration, V: ExpiredVal<E>> SegExpTree<R, E, V>
 where
-    i64: From<R>,
+    R: SegCoord,
 {
     #[inline]
     pub fn new(range: SegRange<R>) -> Option<Self> {
-        let end: i64 = range.max.into();
-        let start: i64 = range.min.into();
-        let layout = Layout::new(start, end)?;
-        let count = layout.count();
+        Self::try_new(range).ok()
+    }
+
+    #[inline]
+    pub fn with_mode(range: SegRange<R>, mode: SegMode) -> Option<Self> {
+        Self::try_with_policy(range, mode, SegPolicy::Clamp).ok()
+    }
+
+    #[inline]
+    pub fn try_new(range: SegRange<R>) -> Result<Self, SegError> {
+        Self::try_with_policy(range, SegMode::Candidate, SegPolicy::Clamp)
+    }
+
+    #[inline]
+    pub fn try_with_policy(range: SegRange<R>, mode: SegMode, policy: SegPolicy) -> Result<Self, SegError> {
+        Self::try_with_heap(range, mode, policy)
+    }
+
+    #[inline]
+    pub fn from_breakpoints(points: &[R]) -> Option<Self> {
+        Self::try_from_breakpoints(points, SegMode::Candidate, SegPolicy::Clamp).ok()
+    }
+}
 
-        Some(Self {
+impl<R, E: Expiration, V: ExpiredVal<E>, H: SegHeap> SegExpTree<R, E, V, H>
+where
+    R: SegCoord,
+{
+    const DEFAULT_REFINE_LIMIT: usize = 256;
+
+    /// Creates a tree with the heap shape `H`, see `Heap64`, `Heap128` and `Heap256`.
+    pub fn try_with_heap(range: SegRange<R>, mode: SegMode, policy: SegPolicy) -> Result<Self, SegError> {
+        let end: i64 = range.max.to_ordered();
+        let start: i64 = range.min.to_ordered();
+        let layout = Layout::new(start, end).ok_or(SegError::InvalidRange)?;
+        Ok(Self::with_layout(layout, mode, policy))
+    }
+
+    /// Creates a tree whose leaves hold equal numbers of the given coordinates instead of
+    /// equal widths. The domain is `min..=max` of `points`. A `Grow` policy falls back to a
+    /// uniform layout once the domain has to grow.
+    pub fn try_from_breakpoints(points: &[R], mode: SegMode, policy: SegPolicy) -> Result<Self, SegError> {
+        let mut ordered: Vec<i64> = points.iter().map(|p| p.to_ordered()).collect();
+        ordered.sort_unstable();
+        ordered.dedup();
+        let layout = Layout::with_breakpoints(&ordered).ok_or(SegError::InvalidRange)?;
+        Ok(Self::with_layout(layout, mode, policy))
+    }
+
+    #[inline]
+    fn with_layout(layout: Layout<H>, mode: SegMode, policy: SegPolicy) -> Self {
+        let chunks = Self::empty_chunks(&layout);
+        Self {
             layout,
-            chunks: vec![Chunk::new(); count],
+            chunks,
+            spans: Spans::new(),
+            mode,
+            policy,
+            refine_limit: Self::DEFAULT_REFINE_LIMIT,
             phantom_data: Default::default(),
-        })
+        }
+    }
+
+    /// Inserts `val` or returns an error if `range` is invalid or rejected by the policy.
+    pub fn try_insert_by_range(&mut self, range: SegRange<R>, val: V) -> Result<(), SegError> {
+        let min: i64 = range.min.to_ordered();
+        let max: i64 = range.max.to_ordered();
+        if self.policy == SegPolicy::Grow && min <= max && !self.layout.contains(min, max) {
+            self.relayout(self.layout.grow(min, max));
+        }
+        let mask = self.place_mask(min, max)?;
+        let span = Span { min, max };
+        let id = self.spans.insert(span);
+        self.insert_entity(Entity { val, mask, id }, span);
+        Ok(())
+    }
+
+    /// A leaf chunk with more than `limit` values splits into a nested heap over its span,
+    /// and merges back when it drops below half of it. `usize::MAX` turns this off.
+    pub fn set_refine_limit(&mut self, limit: usize) {
+        self.refine_limit = limit.max(2);
+    }
+
+    /// Removes every value that expires before `time`. Chunks whose values all live
+    /// until `time` are skipped without a scan.
+    pub fn clear_expired(&mut self, time: E) {
+        let limit = self.refine_limit;
+        for (index, chunk) in self.chunks.iter_mut().enumerate() {
+            chunk.clear_expired(time, index, &mut self.spans);
+            chunk.collapse_if_small(limit);
+        }
+    }
+
+    /// Removes one value inserted with `range` that matches `pred`. Every copy of that value
+    /// is removed, also when a query already dropped some of them as expired.
+    pub fn remove_by_range<P: Fn(&V) -> bool>(&mut self, range: SegRange<R>, pred: P) -> Option<V> {
+        let min: i64 = range.min.to_ordered();
+        let max: i64 = range.max.to_ordered();
+        let Ok(mask) = self.place_mask(min, max) else {
+            return None;
+        };
+        let span = Span { min, max };
+        // a query can drop expired copies from some chunks only, so any copy identifies the value
+        let id =
+            BitIter::new(mask).find_map(|index| self.chunk(index).find(mask, span, &pred, &self.spans))?;
+        let mut result = None;
+        let limit = self.refine_limit;
+        for index in BitIter::new(mask) {
+            let chunk = unsafe { self.chunks.get_unchecked_mut(index) };
+            if let Some(val) = chunk.remove(id, span) {
+                result = Some(val);
+            }
+            chunk.collapse_if_small(limit);
+        }
+        self.spans.remove(id);
+        result
+    }
+
+    /// Moves one value that matches `pred` from `old` to `new`. Returns false and leaves the
+    /// tree unchanged if it is not found, or if `new` is invalid or rejected by the policy.
+    pub fn update_range<P: Fn(&V) -> bool>(&mut self, old: SegRange<R>, new: SegRange<R>, pred: P) -> bool {
+        let min: i64 = new.min.to_ordered();
+        let max: i64 = new.max.to_ordered();
+        if min > max || (self.policy == SegPolicy::Reject && !self.layout.contains(min, max)) {
+            return false;
+        }
+        let Some(val) = self.remove_by_range(old, pred) else {
+            return false;
+        };
+        if self.try_insert_by_range(new, val).is_err() {
+            // `old` was accepted before and a layout only grows
+            let _ = self.try_insert_by_range(old, val);
+            return false;
+        }
+        true
+    }
+
+    /// Keeps only values that match `pred`, which is called once per value.
+    #[inline]
+    pub fn retain<P: FnMut(&V) -> bool>(&mut self, mut pred: P) {
+        for (index, chunk) in self.chunks.iter_mut().enumerate() {
+            chunk.retain(&mut pred, index, &mut self.spans);
+            chunk.collapse_if_small(self.refine_limit);
+        }
+    }
+
+    /// Removes all values and returns each of them once.
+    pub fn drain(&mut self) -> Vec<V> {
+        let mut result = Vec::new();
+        for (index, chunk) in self.chunks.iter_mut().enumerate() {
+            // a value is taken from the first chunk of its mask only
+            result.extend(
+                chunk
+                    .take_entities()
+                    .into_iter()
+                    .filter(|e| e.mask.first_bit() as usize == index && self.spans.contains(e.id))
+                    .map(|e| e.val),
+            );
+        }
+        self.spans.clear();
+        result
+    }
+
+    fn insert_entity(&mut self, entity: Entity<V, H::Mask>, span: Span) {
+        for index in BitIter::new(entity.mask) {
+            let limit = self.refine_limit;
+            let chunk = unsafe { self.chunks.get_unchecked_mut(index) };
+            chunk.insert(entity, span);
+            if chunk.sub.is_none() && chunk.buffer.len() > limit && index >= H::SUB_CAPACITY as usize {
+                let (lo, hi) = self.layout.leaf_span(index as u32 - H::SUB_CAPACITY);
+                // a leaf of one unit can not be split
+                if lo < hi {
+                    chunk.refine(lo, hi, &self.spans);
+                }
+            }
+        }
     }
 
     #[inline]
-    fn chunk(&self, index: usize) -> &Chunk<E, V> {
+    fn empty_chunks(layout: &Layout<H>) -> Vec<Chunk<E, V, H>> {
+        (0..layout.count()).map(|_| Chunk::new()).collect()
+    }
+
+    /// Grows the domain so that `range` fits without a re-layout on insert.
+    pub fn reserve_domain(&mut self, range: SegRange<R>) {
+        let min: i64 = range.min.to_ordered();
+        let max: i64 = range.max.to_ordered();
+        if min <= max && !self.layout.contains(min, max) {
+            self.relayout(self.layout.grow(min, max));
+        }
+    }
+
+    fn relayout(&mut self, layout: Layout<H>) {
+        let mut entities = Vec::new();
+        for (index, chunk) in self.chunks.iter_mut().enumerate() {
+            // every value is moved once, from the first chunk of its old mask
+            entities.extend(
+                chunk
+                    .take_entities()
+                    .into_iter()
+                    .filter(|e| e.mask.first_bit() as usize == index),
+            );
+        }
+        self.chunks = Self::empty_chunks(&layout);
+        self.layout = layout;
+        for mut entity in entities {
+            let Some(span) = self.spans.get(entity.id) else {
+                continue;
+            };
+            let min = self.layout.clamp(span.min);
+            let max = self.layout.clamp(span.max);
+            entity.mask = self.layout.insert_mask(min, max);
+            self.insert_entity(entity, span);
+        }
+    }
+
+    #[inline]
+    fn place_mask(&self, min: i64, max: i64) -> Result<H::Mask, SegError> {
+        if min > max {
+            return Err(SegError::InvalidRange);
+        }
+        if self.policy != SegPolicy::Clamp && !self.layout.contains(min, max) {
+            return Err(SegError::OutOfDomain);
+        }
+        Ok(self
+            .layout
+            .insert_mask(self.layout.clamp(min), self.layout.clamp(max)))
+    }
+
+    #[inline]
+    fn chunk(&self, index: usize) -> &Chunk<E, V, H> {
         unsafe { self.chunks.get_unchecked(index) }
     }
 
     #[inline]
-    fn chunk_mut(&mut self, index: usize) -> &mut Chunk<E, V> {
+    fn chunk_mut(&mut self, index: usize) -> &mut Chunk<E, V, H> {
         unsafe { self.chunks.get_unchecked_mut(index) }
     }
 }
 
-impl<R, E: Expiration, V: ExpiredVal<E>> SegExpCollection<R, E, V> for SegExpTree<R, E, V>
+impl<R, E: Expiration, V: ExpiredVal<E>, H: SegHeap> SegExpCollection<R, E, V> for SegExpTree<R, E, V, H>
 where
-    i64: From<R>,
+    R: SegCoord,
 {
+    /// Panics if `range` is invalid or rejected by the policy, see `try_insert_by_range`.
     #[inline]
     fn insert_by_range(&mut self, range: SegRange<R>, val: V) {
-        let mask = self.layout.insert_mask(range.min.into(), range.max.into());
-        let entity = Entity::new(val, mask);
-        for index in BitIter::new(mask) {
-            self.chunk_mut(index).insert(entity);
+        if let Err(err) = self.try_insert_by_range(range, val) {
+            panic!("{}", err);
         }
     }
 
     type Iter<'a>
-        = SegExpTreeIterator<'a, R, E, V>
+        = SegExpTreeIterator<'a, R, E, V, H>
     where
         R: 'a,
         E: 'a,
-        V: 'a;
+        V: 'a,
+        H: 'a;
+
+    #[inline]
+    fn iter_by_range(&mut self, range: SegRange<R>, time: E) -> SegExpTreeIterator<'_, R, E, V, H> {
+        let min: i64 = range.min.to_ordered();
+        let max: i64 = range.max.to_ordered();
+        // a query is always clamped, values clamped on insert still keep their real bounds
+        let mask = if min > max {
+            H::Mask::ZERO
+        } else {
+            self.layout
+                .intersect_mask(self.layout.clamp(min), self.layout.clamp(max))
+        };
+        let is_exact = self.mode == SegMode::Exact;
+        SegExpTreeIterator::new(mask, min, max, is_exact, time, self)
+    }
 
     #[inline]
-    fn iter_by_range(&mut self, range: SegRange<R>, time: E) -> SegExpTreeIterator<'_, R, E, V> {
-        let mask = self.layout.intersect_mask(range.min.into(), range.max.into());
-        SegExpTreeIterator::new(mask, time, self)
+    fn iter_at_point(&mut self, point: R, time: E) -> SegExpTreeIterator<'_, R, E, V, H> {
+        let p: i64 = point.to_ordered();
+        let mask = self.layout.point_mask(self.layout.clamp(p));
+        // a point is checked against the stored range in both modes
+        SegExpTreeIterator::new(mask, p, p, true, time, self)
     }
 
     #[inline]
@@ -74,31 +313,55 @@ where
         for chunk in self.chunks.iter_mut() {
             chunk.clear();
         }
+        self.spans.clear();
     }
 }
 
-pub struct SegExpTreeIterator<'a, R, E, V> {
-    tree: &'a mut SegExpTree<R, E, V>,
+pub struct SegExpTreeIterator<'a, R, E, V, H: SegHeap = Heap32> {
+    tree: &'a mut SegExpTree<R, E, V, H>,
     time: E,
     i0: usize,
     i1: usize,
-    mask: u64,
-    bit_iter: BitIter,
+    mask: H::Mask,
+    min: i64,
+    max: i64,
+    is_exact: bool,
+    bit_iter: BitIter<H::Mask>,
+    sub: Option<SubIter<H::Mask>>,
 }
 
-impl<'a, R, E: Expiration, V: ExpiredVal<E>> SegExpTreeIterator<'a, R, E, V>
+// the position inside a refined chunk
+struct SubIter<M> {
+    mask: M,
+    j0: usize,
+    j1: usize,
+    bit_iter: BitIter<M>,
+}
+
+impl<'a, R, E: Expiration, V: ExpiredVal<E>, H: SegHeap> SegExpTreeIterator<'a, R, E, V, H>
 where
-    i64: From<R>,
+    R: SegCoord,
 {
     #[inline]
-    fn new(mask: u64, time: E, tree: &'a mut SegExpTree<R, E, V>) -> Self {
+    fn new(
+        mask: H::Mask,
+        min: i64,
+        max: i64,
+        is_exact: bool,
+        time: E,
+        tree: &'a mut SegExpTree<R, E, V, H>,
+    ) -> Self {
         let mut iter = SegExpTreeIterator {
             tree,
             time,
             i0: 0,
             i1: 0,
             mask,
+            min,
+            max,
+            is_exact,
             bit_iter: BitIter::new(mask),
+            sub: None,
         };
 
         // Find the first valid chunk
@@ -116,36 +379,122 @@ where
         }
         usize::MAX
     }
+
+    #[inline]
+    fn is_match(&self, item: &Entity<V, H::Mask>, span: Span) -> bool {
+        // we must return same pair only once, so only for the first index
+        let first_index = item.mask.and(self.mask).first_bit() as usize;
+        first_index == self.i0 && (!self.is_exact || span.is_overlap(self.min, self.max))
+    }
+
+    fn next_in_sub(&mut self) -> Option<V> {
+        let spans = &mut self.tree.spans;
+        let sub = self.tree.chunks[self.i0].sub.as_mut().unwrap();
+        let state = self.sub.get_or_insert_with(|| {
+            let mask = sub
+                .layout
+                .intersect_mask(sub.layout.clamp(self.min), sub.layout.clamp(self.max));
+            let mut bit_iter = BitIter::new(mask);
+            let j0 = bit_iter.next().unwrap_or(usize::MAX);
+            SubIter {
+                mask,
+                j0,
+                j1: 0,
+                bit_iter,
+            }
+        });
+
+        while state.j0 < sub.chunks.len() {
+            let buffer = &mut sub.chunks[state.j0].buffer;
+            let mut i = state.j1;
+            while i < buffer.len() {
+                let SubEntity {
+                    entity: item,
+                    sub_mask,
+                } = buffer[i];
+                // expired and dead copies are dropped on the way
+                let span = if self.is_exact {
+                    spans.get(item.id)
+                } else {
+                    Some(Span::FULL)
+                };
+                let span = match span {
+                    Some(span) if item.val.expiration() >= self.time => span,
+                    _ => {
+                        buffer.swap_remove(i);
+                        if sub_mask.first_bit() as usize == state.j0 {
+                            sub.len -= 1;
+                            if item.mask.first_bit() as usize == self.i0 {
+                                spans.remove(item.id);
+                            }
+                        }
+                        continue;
+                    }
+                };
+                i += 1;
+
+                let first_index = item.mask.and(self.mask).first_bit() as usize;
+                let sub_index = sub_mask.and(state.mask).first_bit() as usize;
+                if first_index == self.i0
+                    && sub_index == state.j0
+                    && (!self.is_exact || span.is_overlap(self.min, self.max))
+                {
+                    state.j1 = i;
+                    return Some(item.val);
+                }
+            }
+            state.j0 = state.bit_iter.next().unwrap_or(usize::MAX);
+            state.j1 = 0;
+        }
+
+        None
+    }
 }
 
-impl<R, E: Expiration, V: ExpiredVal<E>> Iterator for SegExpTreeIterator<'_, R, E, V>
+impl<R, E: Expiration, V: ExpiredVal<E>, H: SegHeap> Iterator for SegExpTreeIterator<'_, R, E, V, H>
 where
-    i64: From<R>,
+    R: SegCoord,
 {
     type Item = V;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
         while self.i0 < self.tree.chunks.len() {
-            let chunk = self.tree.chunk_mut(self.i0);
-            let mut i = self.i1;
-            while i < chunk.buffer.len() {
-                let item = chunk.entity(i);
-
-                if item.val.expiration() < self.time {
-                    chunk.buffer.swap_remove(i);
-                    continue;
+            if self.tree.chunk(self.i0).sub.is_some() {
+                if let Some(val) = self.next_in_sub() {
+                    return Some(val);
                 }
-                i += 1;
+                self.sub = None;
+                let limit = self.tree.refine_limit;
+                self.tree.chunk_mut(self.i0).collapse_if_small(limit);
+            } else {
+                let mut i = self.i1;
+                while i < self.tree.chunk(self.i0).buffer.len() {
+                    let tree = &mut *self.tree;
+                    let chunk = unsafe { tree.chunks.get_unchecked_mut(self.i0) };
+                    let item = *chunk.entity(i);
 
-                // we must return same pair only once,
-                let mask_int = item.mask & self.mask;
-                let first_index = mask_int.trailing_zeros() as usize;
+                    let span = if self.is_exact {
+                        tree.spans.get(item.id)
+                    } else {
+                        Some(Span::FULL)
+                    };
+                    let span = match span {
+                        Some(span) if item.val.expiration() >= self.time => span,
+                        _ => {
+                            chunk.buffer.swap_remove(i);
+                            if item.mask.first_bit() as usize == self.i0 {
+                                tree.spans.remove(item.id);
+                            }
+                            continue;
+                        }
+                    };
+                    i += 1;
 
-                // we will return only for first index
-                if first_index == self.i0 {
-                    self.i1 = i;
-                    return Some(item.val);
+                    if self.is_match(&item, span) {
+                        self.i1 = i;
+                        return Some(item.val);
+                    }
                 }
             }
 
@@ -160,7 +509,9 @@ where
 #[cfg(test)]
 mod tests {
     use crate::ExpiredVal;
-    use crate::seg::exp::{SegExpCollection, SegRange};
+    use crate::seg::coord::Scaled;
+    use crate::seg::exp::{SegError, SegExpCollection, SegMode, SegPolicy, SegRange};
+    use crate::seg::heap::{Heap32, Heap64, Heap128, Heap256, SegHeap};
     use crate::seg::tree::SegExpTree;
     use alloc::vec::Vec;
 
@@ -287,4 +638,533 @@ mod tests {
         }
         assert_eq!(result.len(), 1);
     }
+
+    #[test]
+    fn test_05() {
+        // both ranges fall into the same bucket of a wide layout
+        let range = SegRange { min: 0, max: 4095 };
+        let mut candidate = SegExpTree::new(range).unwrap();
+        let mut exact = SegExpTree::with_mode(range, SegMode::Exact).unwrap();
+        let s = Segment::new(0, 10, 2, 20);
+        candidate.insert_by_range(s.y_range(), s);
+        exact.insert_by_range(s.y_range(), s);
+
+        let q = SegRange { min: 40, max: 60 };
+        assert_eq!(candidate.iter_by_range(q, 0).count(), 1);
+        assert_eq!(exact.iter_by_range(q, 0).count(), 0);
+
+        let q = SegRange { min: 20, max: 60 };
+        assert_eq!(exact.iter_by_range(q, 0).count(), 1);
+    }
+
+    // `a.x` is the index, a third of the segments spread over 0..10_000 and the rest are
+    // packed into 500..800
+    fn segments(n: i32, seed: i32) -> Vec<Segment> {
+        (0..n)
+            .map(|i| {
+                let k = i + seed;
+                let y0 = if k % 3 == 0 {
+                    (k * 7919) % 9900
+                } else {
+                    500 + (k * 37) % 200
+                };
+                Segment::new(i, y0, 10 + (k * 13) % 90, y0 + (k * 31) % 100)
+            })
+            .collect()
+    }
+
+    // compares queries on a grid and around every segment end with a scan of `segments`,
+    // a range query in candidate mode can return more values than overlap
+    fn assert_matches_brute_force<H: SegHeap>(
+        tree: &mut SegExpTree<i32, i32, Segment, H>,
+        segments: &[Segment],
+        time: i32,
+    ) {
+        let alive: Vec<SegRange<i32>> = segments
+            .iter()
+            .filter(|s| s.expiration() >= time)
+            .map(|s| s.y_range())
+            .collect();
+        let count = |min: i32, max: i32| alive.iter().filter(|r| r.min <= max && min <= r.max).count();
+
+        let lo = segments.iter().map(|s| s.y_range().min).min().unwrap_or(0);
+        let hi = segments.iter().map(|s| s.y_range().max).max().unwrap_or(0);
+        let step = ((hi - lo) / 400).max(1);
+        let edges = segments.iter().flat_map(|s| {
+            let r = s.y_range();
+            [r.min - 1, r.min, r.max, r.max + 1]
+        });
+        for p in (lo - 1..=hi + 1).step_by(step as usize).chain(edges) {
+            assert_eq!(tree.iter_at_point(p, time).count(), count(p, p));
+
+            let q = SegRange {
+                min: p,
+                max: p + 3 * step,
+            };
+            let n = tree.iter_by_range(q, time).count();
+            if tree.mode == SegMode::Exact {
+                assert_eq!(n, count(q.min, q.max));
+            } else {
+                assert!(n >= count(q.min, q.max));
+            }
+        }
+    }
+
+    #[test]
+    fn test_06() {
+        let range = SegRange { min: 0, max: 10_000 };
+        let mut tree = SegExpTree::with_mode(range, SegMode::Exact).unwrap();
+        let segments = segments(200, 0);
+        for s in segments.iter() {
+            tree.insert_by_range(s.y_range(), *s);
+        }
+        assert_matches_brute_force(&mut tree, &segments, 0);
+    }
+
+    #[test]
+    fn test_07() {
+        let mut tree = SegExpTree::new(SegRange { min: 0, max: 10_000 }).unwrap();
+        let segments = segments(200, 1);
+        for s in segments.iter() {
+            tree.insert_by_range(s.y_range(), *s);
+        }
+        for time in [0, 20, 40, 150] {
+            assert_matches_brute_force(&mut tree, &segments, time);
+        }
+    }
+
+    #[test]
+    fn test_08() {
+        let mut tree = SegExpTree::new(SegRange { min: 0, max: 10_000 }).unwrap();
+        let mut segments = segments(100, 2);
+        for s in segments.iter() {
+            tree.insert_by_range(s.y_range(), *s);
+        }
+
+        // remove every third segment
+        for s in segments.iter().step_by(3) {
+            let removed = tree.remove_by_range(s.y_range(), |v| v.a.x == s.a.x);
+            assert_eq!(removed.map(|v| v.a.x), Some(s.a.x));
+            assert!(tree.remove_by_range(s.y_range(), |v| v.a.x == s.a.x).is_none());
+        }
+        segments.retain(|s| s.a.x % 3 != 0);
+
+        // move the rest one step up
+        for s in segments.iter_mut() {
+            let old = s.y_range();
+            s.a.y += 1;
+            s.b.y += 1;
+            assert!(tree.update_range(old, s.y_range(), |v| v.a.x == s.a.x));
+        }
+        let mut moved = segments.clone();
+        for s in moved.iter_mut() {
+            s.a.y -= 1;
+            s.b.y -= 1;
+            assert!(!tree.update_range(s.y_range(), s.y_range(), |v| v.a.x == s.a.x));
+        }
+
+        assert_matches_brute_force(&mut tree, &segments, 0);
+
+        // the predicate sees every value once
+        let mut seen = Vec::new();
+        tree.retain(|v| {
+            seen.push(v.a.x);
+            v.a.x % 2 == 0
+        });
+        seen.sort_unstable();
+        assert!(seen.iter().copied().eq(segments.iter().map(|s| s.a.x)));
+        segments.retain(|s| s.a.x % 2 == 0);
+
+        let mut drained: Vec<i32> = tree.drain().iter().map(|v| v.a.x).collect();
+        drained.sort_unstable();
+        let template: Vec<i32> = segments.iter().map(|s| s.a.x).collect();
+        assert_eq!(drained, template);
+        assert!(tree.chunks.iter().all(|c| c.is_empty()));
+    }
+
+    #[test]
+    fn test_09() {
+        let mut tree = SegExpTree::new(SegRange { min: 3, max: 7 }).unwrap();
+        for y in 3..=7 {
+            let s = Segment::new(0, y, 10, y);
+            tree.insert_by_range(s.y_range(), s);
+        }
+        for y in 3..=7 {
+            assert_eq!(tree.iter_at_point(y, 0).count(), 1);
+            assert_eq!(
+                tree.iter_by_range(SegRange { min: 3, max: y }, 0).count(),
+                (y - 2) as usize
+            );
+        }
+        assert_eq!(
+            SegExpTree::<i32, i32, Segment>::try_new(SegRange { min: 7, max: 3 }).err(),
+            Some(SegError::InvalidRange)
+        );
+    }
+
+    #[test]
+    fn test_10() {
+        let range = SegRange { min: 0, max: 100 };
+        let mut reject = SegExpTree::try_with_policy(range, SegMode::Exact, SegPolicy::Reject).unwrap();
+        let mut clamp = SegExpTree::try_with_policy(range, SegMode::Exact, SegPolicy::Clamp).unwrap();
+
+        let inside = Segment::new(0, 10, 10, 20);
+        let outside = Segment::new(0, -50, 10, -20);
+        let across = Segment::new(0, 90, 10, 150);
+        let invalid = SegRange { min: 5, max: 1 };
+
+        assert_eq!(reject.try_insert_by_range(inside.y_range(), inside), Ok(()));
+        assert_eq!(
+            reject.try_insert_by_range(outside.y_range(), outside),
+            Err(SegError::OutOfDomain)
+        );
+        assert_eq!(
+            reject.try_insert_by_range(across.y_range(), across),
+            Err(SegError::OutOfDomain)
+        );
+        assert_eq!(
+            reject.try_insert_by_range(invalid, inside),
+            Err(SegError::InvalidRange)
+        );
+        assert!(reject.remove_by_range(outside.y_range(), |_| true).is_none());
+
+        for s in [inside, outside, across] {
+            assert_eq!(clamp.try_insert_by_range(s.y_range(), s), Ok(()));
+        }
+        assert_eq!(
+            clamp.try_insert_by_range(invalid, inside),
+            Err(SegError::InvalidRange)
+        );
+
+        assert_eq!(clamp.iter_at_point(-30, 0).count(), 1);
+        assert_eq!(clamp.iter_at_point(-10, 0).count(), 0);
+        assert_eq!(clamp.iter_at_point(120, 0).count(), 1);
+        assert_eq!(
+            clamp.iter_by_range(SegRange { min: -200, max: 200 }, 0).count(),
+            3
+        );
+        assert_eq!(clamp.iter_by_range(SegRange { min: 200, max: 300 }, 0).count(), 0);
+
+        assert!(clamp.remove_by_range(outside.y_range(), |_| true).is_some());
+        assert_eq!(clamp.iter_at_point(-30, 0).count(), 0);
+
+        // a rejected move keeps the value at its old range
+        assert!(!reject.update_range(inside.y_range(), outside.y_range(), |_| true));
+        assert!(!reject.update_range(inside.y_range(), invalid, |_| true));
+        assert!(!clamp.update_range(inside.y_range(), invalid, |_| true));
+        assert_eq!(reject.iter_at_point(15, 0).count(), 1);
+        assert_eq!(clamp.iter_at_point(15, 0).count(), 1);
+        assert!(reject.update_range(inside.y_range(), SegRange { min: 50, max: 60 }, |_| true));
+        assert_eq!(reject.iter_at_point(15, 0).count(), 0);
+        assert_eq!(reject.iter_at_point(55, 0).count(), 1);
+    }
+
+    #[test]
+    fn test_11() {
+        let range = SegRange { min: 0, max: 31 };
+        let mut tree = SegExpTree::try_with_policy(range, SegMode::Exact, SegPolicy::Grow).unwrap();
+        let segments = segments(300, 3);
+        for s in segments.iter() {
+            assert_eq!(tree.try_insert_by_range(s.y_range(), *s), Ok(()));
+        }
+        assert!(tree.layout.contains(0, 10_000));
+
+        for time in [0, 20] {
+            assert_matches_brute_force(&mut tree, &segments, time);
+        }
+
+        tree.reserve_domain(SegRange {
+            min: -100_000,
+            max: 100_000,
+        });
+        assert!(tree.layout.contains(-100_000, 100_000));
+        tree.retain(|v| v.expiration() >= 20);
+        let alive = segments.iter().filter(|s| s.expiration() >= 20).count();
+        assert_eq!(tree.drain().len(), alive);
+    }
+
+    #[derive(Clone, Copy)]
+    struct Span {
+        min: f64,
+        max: f64,
+    }
+
+    impl ExpiredVal<i32> for Span {
+        fn expiration(&self) -> i32 {
+            100
+        }
+    }
+
+    #[test]
+    fn test_12() {
+        let spans: Vec<Span> = (0..100)
+            .map(|i| {
+                let min = (i as f64 * 0.37) % 10.0 - 5.0;
+                Span {
+                    min,
+                    max: min + (i % 7) as f64 * 0.3,
+                }
+            })
+            .collect();
+
+        let domain = SegRange { min: -5.0, max: 7.0 };
+        let mut bits = SegExpTree::with_mode(domain, SegMode::Exact).unwrap();
+        let scaled_domain = SegRange {
+            min: Scaled::<f64, 1000>(-5.0),
+            max: Scaled(7.0),
+        };
+        let mut scaled = SegExpTree::with_mode(scaled_domain, SegMode::Exact).unwrap();
+        for s in spans.iter() {
+            bits.insert_by_range(
+                SegRange {
+                    min: s.min,
+                    max: s.max,
+                },
+                *s,
+            );
+            scaled.insert_by_range(
+                SegRange {
+                    min: Scaled(s.min),
+                    max: Scaled(s.max),
+                },
+                *s,
+            );
+        }
+
+        for i in 0..120 {
+            let p = -5.0 + i as f64 * 0.1;
+            let count = spans.iter().filter(|s| s.min <= p && p <= s.max).count();
+            assert_eq!(bits.iter_at_point(p, 0).count(), count);
+
+            let q = SegRange {
+                min: p,
+                max: p + 0.25,
+            };
+            let count = spans.iter().filter(|s| s.min <= q.max && q.min <= s.max).count();
+            assert_eq!(bits.iter_by_range(q, 0).count(), count);
+
+            // the scaled tree compares quantized bounds
+            let q = |x: f64| (x * 1000.0) as i64;
+            let count = spans
+                .iter()
+                .filter(|s| q(s.min) <= q(p) && q(p) <= q(s.max))
+                .count();
+            assert_eq!(scaled.iter_at_point(Scaled(p), 0).count(), count);
+        }
+    }
+
+    fn count_candidates<H: SegHeap>(segments: &[Segment]) -> usize {
+        let range = SegRange { min: 0, max: 10_000 };
+        let mut exact: SegExpTree<i32, i32, Segment, H> =
+            SegExpTree::try_with_heap(range, SegMode::Exact, SegPolicy::Reject).unwrap();
+        let mut candidate: SegExpTree<i32, i32, Segment, H> =
+            SegExpTree::try_with_heap(range, SegMode::Candidate, SegPolicy::Reject).unwrap();
+        for s in segments.iter() {
+            exact.insert_by_range(s.y_range(), *s);
+            candidate.insert_by_range(s.y_range(), *s);
+        }
+        assert_matches_brute_force(&mut exact, segments, 0);
+        assert_matches_brute_force(&mut candidate, segments, 0);
+
+        let candidates = (0..10_000)
+            .step_by(97)
+            .map(|q0| {
+                let q = SegRange {
+                    min: q0,
+                    max: (q0 + 40).min(10_000),
+                };
+                candidate.iter_by_range(q, 0).count()
+            })
+            .sum();
+
+        let mut drained: Vec<i32> = candidate.drain().iter().map(|s| s.a.x).collect();
+        drained.sort_unstable();
+        assert_eq!(drained, segments.iter().map(|s| s.a.x).collect::<Vec<_>>());
+
+        candidates
+    }
+
+    #[test]
+    fn test_13() {
+        // only the spread segments, a packed leaf gives the same candidates in every heap
+        let segments: Vec<Segment> = segments(1500, 0).into_iter().filter(|s| s.a.x % 3 == 0).collect();
+        let c32 = count_candidates::<Heap32>(&segments);
+        let c64 = count_candidates::<Heap64>(&segments);
+        let c128 = count_candidates::<Heap128>(&segments);
+        let c256 = count_candidates::<Heap256>(&segments);
+        assert!(c32 > c64 && c64 > c128 && c128 > c256);
+    }
+
+    #[test]
+    fn test_14() {
+        let range = SegRange { min: 0, max: 10_000 };
+        let mut tree = SegExpTree::with_mode(range, SegMode::Exact).unwrap();
+        tree.set_refine_limit(16);
+        let segments = segments(600, 5);
+        for s in segments.iter() {
+            tree.insert_by_range(s.y_range(), *s);
+        }
+        assert!(tree.chunks.iter().any(|c| c.sub.is_some()));
+        assert_matches_brute_force(&mut tree, &segments, 0);
+
+        for s in segments.iter().filter(|s| s.a.x % 3 == 0) {
+            let removed = tree.remove_by_range(s.y_range(), |v| v.a.x == s.a.x);
+            assert_eq!(removed.map(|v| v.a.x), Some(s.a.x));
+        }
+        let mut segments: Vec<Segment> = segments.into_iter().filter(|s| s.a.x % 3 != 0).collect();
+        assert_matches_brute_force(&mut tree, &segments, 0);
+        assert_matches_brute_force(&mut tree, &segments, 30);
+
+        let mut seen = Vec::new();
+        tree.retain(|v| {
+            seen.push(v.a.x);
+            v.a.x % 50 == 1
+        });
+        let len = seen.len();
+        seen.sort_unstable();
+        seen.dedup();
+        assert_eq!(seen.len(), len);
+        segments.retain(|s| s.a.x % 50 == 1);
+        assert!(tree.chunks.iter().all(|c| c.sub.is_none()));
+        assert_matches_brute_force(&mut tree, &segments, 30);
+
+        let mut drained: Vec<i32> = tree.drain().iter().map(|s| s.a.x).collect();
+        drained.sort_unstable();
+        let template: Vec<i32> = segments
+            .iter()
+            .filter(|s| s.expiration() >= 30)
+            .map(|s| s.a.x)
+            .collect();
+        assert_eq!(drained, template);
+    }
+
+    #[test]
+    fn test_15() {
+        // a dense cluster near zero and a sparse tail
+        let segments: Vec<Segment> = (0..400)
+            .map(|i| {
+                let y0 = if i % 4 == 0 { i * i * 10 } else { i % 97 };
+                Segment::new(i, y0, 100, y0 + i % 5)
+            })
+            .collect();
+        let points: Vec<i32> = segments.iter().flat_map(|s| [s.a.y, s.b.y]).collect();
+
+        let mut uniform = SegExpTree::new(SegRange {
+            min: 0,
+            max: *points.iter().max().unwrap(),
+        })
+        .unwrap();
+        let mut compressed = SegExpTree::from_breakpoints(&points).unwrap();
+        for s in segments.iter() {
+            uniform.insert_by_range(s.y_range(), *s);
+            compressed.insert_by_range(s.y_range(), *s);
+        }
+
+        assert_matches_brute_force(&mut compressed, &segments, 0);
+
+        let mut uniform_count = 0;
+        let mut compressed_count = 0;
+        for q0 in 0..120 {
+            let q = SegRange { min: q0, max: q0 + 2 };
+            compressed_count += compressed.iter_by_range(q, 0).count();
+            uniform_count += uniform.iter_by_range(q, 0).count();
+        }
+        assert!(compressed_count < uniform_count);
+
+        assert!(SegExpTree::<i32, i32, Segment>::from_breakpoints(&[]).is_none());
+    }
+
+    #[test]
+    fn test_16() {
+        let range = SegRange { min: 0, max: 10_000 };
+        let mut tree = SegExpTree::with_mode(range, SegMode::Exact).unwrap();
+        tree.set_refine_limit(16);
+        let mut segments = segments(500, 6);
+        for s in segments.iter() {
+            tree.insert_by_range(s.y_range(), *s);
+        }
+
+        for time in [5, 30, 60, 99, 100, 500] {
+            tree.clear_expired(time);
+            segments.retain(|s| s.expiration() >= time);
+            let stored: usize = tree
+                .chunks
+                .iter()
+                .map(|c| {
+                    c.buffer.len()
+                        + c.sub
+                            .as_ref()
+                            .map_or(0, |s| s.chunks.iter().map(|c| c.buffer.len()).sum())
+                })
+                .sum();
+            let expired = tree.chunks.iter().any(|c| {
+                c.buffer.iter().any(|e| e.val.expiration() < time)
+                    || c.sub.as_ref().is_some_and(|s| {
+                        s.chunks
+                            .iter()
+                            .any(|c| c.buffer.iter().any(|e| e.entity.val.expiration() < time))
+                    })
+            });
+            assert!(!expired);
+            assert!(stored >= segments.len());
+            assert!(tree.chunks.iter().all(|c| c.is_empty() || c.min_exp >= time));
+
+            assert_matches_brute_force(&mut tree, &segments, time);
+        }
+        assert!(segments.is_empty());
+        assert!(tree.chunks.iter().all(|c| c.is_empty()));
+    }
+
+    #[test]
+    fn test_17() {
+        // a query drops the expired copy of the leaf it visits, the other copy stays
+        let mut tree = SegExpTree::new(SegRange { min: 0, max: 31 }).unwrap();
+        let s = Segment::new(0, 0, 5, 2);
+        tree.insert_by_range(s.y_range(), s);
+        assert_eq!(tree.iter_by_range(SegRange { min: 2, max: 2 }, 10).count(), 0);
+        assert!(tree.remove_by_range(s.y_range(), |_| true).is_some());
+        assert_eq!(tree.iter_at_point(0, 0).count(), 0);
+        assert!(tree.chunks.iter().all(|c| c.is_empty()));
+
+        // once the first copy is dropped the value is gone, the other copy is dead
+        tree.insert_by_range(s.y_range(), s);
+        assert_eq!(tree.iter_by_range(SegRange { min: 0, max: 0 }, 10).count(), 0);
+        assert_eq!(tree.iter_at_point(2, 0).count(), 0);
+        assert!(tree.remove_by_range(s.y_range(), |_| true).is_none());
+
+        // a loose predicate removes every copy of one value
+        let a = Segment::new(1, 0, 100, 2);
+        let b = Segment::new(2, 0, 100, 2);
+        tree.insert_by_range(a.y_range(), a);
+        tree.insert_by_range(b.y_range(), b);
+        let removed = tree.remove_by_range(a.y_range(), |_| true).unwrap();
+        for p in 0..=2 {
+            let left: Vec<i32> = tree.iter_at_point(p, 0).map(|v| v.a.x).collect();
+            assert_eq!(left.len(), 1);
+            assert_ne!(left[0], removed.a.x);
+        }
+    }
+
+    #[test]
+    fn test_18() {
+        let mut tree = SegExpTree::new(SegRange { min: 0, max: 1023 }).unwrap();
+        tree.set_refine_limit(4);
+        // all values share the first leaf, the expired ones are also placed at point 10
+        let segments: Vec<Segment> = (0..8)
+            .map(|i| Segment::new(i, 4, 5, 10))
+            .chain((8..12).map(|i| Segment::new(i, 2, 50, 12)))
+            .collect();
+        for s in segments.iter() {
+            tree.insert_by_range(s.y_range(), *s);
+        }
+        assert!(tree.chunks.iter().any(|c| c.sub.is_some()));
+
+        // the query drops some copies of the expired values inside the sub-heap
+        assert_eq!(tree.iter_by_range(SegRange { min: 10, max: 10 }, 10).count(), 4);
+        for s in segments.iter() {
+            let removed = tree.remove_by_range(s.y_range(), |v| v.a.x == s.a.x);
+            assert_eq!(removed.map(|v| v.a.x), Some(s.a.x));
+        }
+        assert!(tree.chunks.iter().all(|c| c.is_empty()));
+    }
 }
@@ -0,0 +1,283 @@
+use crate::EMPTY_REF;
+use crate::id_index::IdIndex;
+use crate::set::iter::TreeIterator;
+use crate::set::list::SetList;
+use crate::set::node::Color;
+use crate::set::sort::KeyValue;
+use crate::set::summary::Summary;
+use crate::set::tree::SetTree;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::iter::Peekable;
+use core::marker::PhantomData;
+use core::slice;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Union,
+    Intersection,
+    Difference,
+    SymmetricDifference,
+}
+
+/// A lazy merge of two sorted sequences. Values with equal keys are taken from the first one.
+///
+/// Equal keys within one sequence are matched one to one with equal keys of the other.
+pub struct Merge<'a, K, V: 'a, I: Iterator<Item = &'a V>> {
+    a: Peekable<I>,
+    b: Peekable<I>,
+    operation: Operation,
+    phantom_data: PhantomData<K>,
+}
+
+impl<'a, K, V: 'a, I: Iterator<Item = &'a V>> Merge<'a, K, V, I> {
+    #[inline]
+    fn new(a: I, b: I, operation: Operation) -> Self {
+        Self {
+            a: a.peekable(),
+            b: b.peekable(),
+            operation,
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<'a, K: Ord, V: KeyValue<K> + 'a, I: Iterator<Item = &'a V>> Iterator for Merge<'a, K, V, I> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.a.peek(), self.b.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(a), Some(b)) => a.key().cmp(b.key()),
+            };
+
+            match order {
+                Ordering::Less => {
+                    let a = self.a.next();
+                    if self.operation != Operation::Intersection {
+                        return a;
+                    }
+                    // nothing left to intersect with
+                    self.b.peek()?;
+                }
+                Ordering::Greater => {
+                    let b = self.b.next();
+                    match self.operation {
+                        Operation::Union | Operation::SymmetricDifference => return b,
+                        _ => {
+                            self.a.peek()?;
+                        }
+                    }
+                }
+                Ordering::Equal => {
+                    let a = self.a.next();
+                    self.b.next();
+                    match self.operation {
+                        Operation::Union | Operation::Intersection => return a,
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default> SetTree<K, V> {
+    /// Builds a tree from values sorted by key in O(n).
+    ///
+    /// Values with equal keys keep their order, as if they were inserted one by one.
+    #[inline]
+    pub fn from_sorted<I: IntoIterator<Item = V>>(values: I) -> Self {
+        Self::from_sorted_with_summary(values)
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> SetTree<K, V, S> {
+    /// See `from_sorted`.
+    #[inline]
+    pub fn from_sorted_with_summary<I: IntoIterator<Item = V>>(values: I) -> Self {
+        Self::build_sorted(values, None)
+    }
+
+    // `ids` must be empty, it is filled while the tree is built
+    fn build_sorted<I: IntoIterator<Item = V>>(values: I, ids: Option<IdIndex<V>>) -> Self {
+        let values: Vec<V> = values.into_iter().collect();
+        debug_assert!(values.windows(2).all(|w| w[0].key() <= w[1].key()));
+
+        let n = values.len();
+        let mut tree = Self::with_summary(n + 1);
+        tree.ids = ids;
+        // the last level of a balanced tree is red, so every path has the same black height
+        let red_depth = (n + 1).ilog2();
+        let mut iter = values.into_iter();
+        let root = tree.build(&mut iter, n, 0, red_depth, EMPTY_REF);
+        tree.root = root;
+        tree
+    }
+
+    fn build<I: Iterator<Item = V>>(
+        &mut self,
+        iter: &mut I,
+        count: usize,
+        depth: u32,
+        red_depth: u32,
+        parent: u32,
+    ) -> u32 {
+        if count == 0 {
+            return EMPTY_REF;
+        }
+        let index = self.store.get_free_index();
+        let left_count = count / 2;
+        let left = self.build(iter, left_count, depth + 1, red_depth, index);
+        let value = iter.next().unwrap();
+        let right = self.build(iter, count - left_count - 1, depth + 1, red_depth, index);
+
+        let node = self.node_mut(index);
+        node.parent = parent;
+        node.left = left;
+        node.right = right;
+        node.color = if depth >= red_depth {
+            Color::Red
+        } else {
+            Color::Black
+        };
+        node.value = value;
+        self.register_id(index);
+        self.update_summary(index);
+
+        index
+    }
+
+    #[inline]
+    pub fn union<'a>(&'a self, other: &'a Self) -> Merge<'a, K, V, TreeIterator<'a, K, V, S>> {
+        Merge::new(self.iter(), other.iter(), Operation::Union)
+    }
+
+    #[inline]
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> Merge<'a, K, V, TreeIterator<'a, K, V, S>> {
+        Merge::new(self.iter(), other.iter(), Operation::Intersection)
+    }
+
+    #[inline]
+    pub fn difference<'a>(&'a self, other: &'a Self) -> Merge<'a, K, V, TreeIterator<'a, K, V, S>> {
+        Merge::new(self.iter(), other.iter(), Operation::Difference)
+    }
+
+    #[inline]
+    pub fn symmetric_difference<'a>(&'a self, other: &'a Self) -> Merge<'a, K, V, TreeIterator<'a, K, V, S>> {
+        Merge::new(self.iter(), other.iter(), Operation::SymmetricDifference)
+    }
+
+    /// Builds the union as a new tree. Like the other `*_of` builders it gets an id index
+    /// if `a` has one.
+    #[inline]
+    pub fn union_of(a: &Self, b: &Self) -> Self {
+        Self::build_sorted(a.union(b).cloned(), a.ids.as_ref().map(IdIndex::empty_like))
+    }
+
+    #[inline]
+    pub fn intersection_of(a: &Self, b: &Self) -> Self {
+        Self::build_sorted(
+            a.intersection(b).cloned(),
+            a.ids.as_ref().map(IdIndex::empty_like),
+        )
+    }
+
+    #[inline]
+    pub fn difference_of(a: &Self, b: &Self) -> Self {
+        Self::build_sorted(a.difference(b).cloned(), a.ids.as_ref().map(IdIndex::empty_like))
+    }
+
+    #[inline]
+    pub fn symmetric_difference_of(a: &Self, b: &Self) -> Self {
+        Self::build_sorted(
+            a.symmetric_difference(b).cloned(),
+            a.ids.as_ref().map(IdIndex::empty_like),
+        )
+    }
+}
+
+impl<V> SetList<V> {
+    /// Builds a list from values sorted by key.
+    #[inline]
+    pub fn from_sorted<K: Ord, I: IntoIterator<Item = V>>(values: I) -> Self
+    where
+        V: KeyValue<K>,
+    {
+        let mut list = Self::new(0);
+        list.buffer = values.into_iter().collect();
+        debug_assert!(list.buffer.windows(2).all(|w| w[0].key() <= w[1].key()));
+        list
+    }
+
+    #[inline]
+    pub fn union<'a, K: Ord>(&'a self, other: &'a Self) -> Merge<'a, K, V, slice::Iter<'a, V>>
+    where
+        V: KeyValue<K>,
+    {
+        Merge::new(self.buffer.iter(), other.buffer.iter(), Operation::Union)
+    }
+
+    #[inline]
+    pub fn intersection<'a, K: Ord>(&'a self, other: &'a Self) -> Merge<'a, K, V, slice::Iter<'a, V>>
+    where
+        V: KeyValue<K>,
+    {
+        Merge::new(self.buffer.iter(), other.buffer.iter(), Operation::Intersection)
+    }
+
+    #[inline]
+    pub fn difference<'a, K: Ord>(&'a self, other: &'a Self) -> Merge<'a, K, V, slice::Iter<'a, V>>
+    where
+        V: KeyValue<K>,
+    {
+        Merge::new(self.buffer.iter(), other.buffer.iter(), Operation::Difference)
+    }
+
+    #[inline]
+    pub fn symmetric_difference<'a, K: Ord>(&'a self, other: &'a Self) -> Merge<'a, K, V, slice::Iter<'a, V>>
+    where
+        V: KeyValue<K>,
+    {
+        Merge::new(
+            self.buffer.iter(),
+            other.buffer.iter(),
+            Operation::SymmetricDifference,
+        )
+    }
+
+    #[inline]
+    pub fn union_of<K: Ord>(a: &Self, b: &Self) -> Self
+    where
+        V: KeyValue<K> + Clone,
+    {
+        Self::from_sorted(a.union(b).cloned())
+    }
+
+    #[inline]
+    pub fn intersection_of<K: Ord>(a: &Self, b: &Self) -> Self
+    where
+        V: KeyValue<K> + Clone,
+    {
+        Self::from_sorted(a.intersection(b).cloned())
+    }
+
+    #[inline]
+    pub fn difference_of<K: Ord>(a: &Self, b: &Self) -> Self
+    where
+        V: KeyValue<K> + Clone,
+    {
+        Self::from_sorted(a.difference(b).cloned())
+    }
+
+    #[inline]
+    pub fn symmetric_difference_of<K: Ord>(a: &Self, b: &Self) -> Self
+    where
+        V: KeyValue<K> + Clone,
+    {
+        Self::from_sorted(a.symmetric_difference(b).cloned())
+    }
+}
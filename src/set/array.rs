@@ -0,0 +1,23 @@
+use crate::set::list::SetList;
+use crate::set::sort::KeyValue;
+use crate::set::summary::Summary;
+use crate::set::tree::SetTree;
+use alloc::vec::Vec;
+
+pub trait IntoArray<V> {
+    fn into_sorted_vec(self) -> Vec<V>;
+}
+
+impl<V> IntoArray<V> for SetList<V> {
+    #[inline]
+    fn into_sorted_vec(self) -> Vec<V> {
+        self.buffer
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> IntoArray<V> for SetTree<K, V, S> {
+    #[inline]
+    fn into_sorted_vec(self) -> Vec<V> {
+        self.into_iter().collect()
+    }
+}
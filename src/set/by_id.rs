@@ -0,0 +1,57 @@
+use crate::id_index::IdIndex;
+use crate::set::sort::KeyValue;
+use crate::set::summary::Summary;
+use crate::set::tree::SetTree;
+use crate::{EMPTY_REF, HasId};
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default + HasId> SetTree<K, V> {
+    /// Creates a tree that can also find a value by `HasId::id`. Ids do not have to be dense.
+    #[inline]
+    pub fn with_id_index(capacity: usize) -> Self {
+        let mut tree = Self::new(capacity);
+        tree.ids = Some(IdIndex::new(V::id));
+        tree
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default + HasId, S: Summary<V>> SetTree<K, V, S> {
+    /// Node index of the value with `id`, or `None` if the tree has no id index.
+    #[inline]
+    pub fn position_of(&self, id: usize) -> Option<u32> {
+        let index = self.ids.as_ref()?.get(id);
+        // the entry of a forgotten guard can point at a value whose id has changed
+        if index == EMPTY_REF || self.node(index).value.id() != id {
+            None
+        } else {
+            Some(index)
+        }
+    }
+
+    #[inline]
+    pub fn remove_by_id(&mut self, id: usize) -> Option<V> {
+        let index = self.position_of(id)?;
+        let val = self.node(index).value.clone();
+        self.delete_index(index);
+        Some(val)
+    }
+
+    #[inline]
+    pub fn next_by_id(&self, id: usize) -> Option<&V> {
+        let index = self.next_index(self.position_of(id)?);
+        if index == EMPTY_REF {
+            None
+        } else {
+            Some(&self.node(index).value)
+        }
+    }
+
+    #[inline]
+    pub fn prev_by_id(&self, id: usize) -> Option<&V> {
+        let index = self.prev_index(self.position_of(id)?);
+        if index == EMPTY_REF {
+            None
+        } else {
+            Some(&self.node(index).value)
+        }
+    }
+}
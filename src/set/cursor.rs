@@ -0,0 +1,158 @@
+use crate::set::handle::Handle;
+use crate::set::sort::SetCollection;
+use core::marker::PhantomData;
+
+/// A position on a value of a `SetTree` or `SetList` that can step to its neighbors.
+///
+/// Once the cursor steps past either end it is no longer on a value and stays there.
+pub struct Cursor<'a, K, V, C> {
+    set: &'a C,
+    handle: Option<Handle>,
+    phantom_data: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, C: SetCollection<K, V>> Cursor<'a, K, V, C> {
+    #[inline]
+    pub fn new(set: &'a C, handle: Handle) -> Self {
+        let handle = set.get(handle).map(|_| handle);
+        Self {
+            set,
+            handle,
+            phantom_data: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn handle(&self) -> Option<Handle> {
+        self.handle
+    }
+
+    #[inline]
+    pub fn current(&self) -> Option<&'a V> {
+        self.set.get(self.handle?)
+    }
+
+    #[inline]
+    pub fn peek_next(&self) -> Option<&'a V> {
+        self.set.get(self.set.next(self.handle?)?)
+    }
+
+    #[inline]
+    pub fn peek_prev(&self) -> Option<&'a V> {
+        self.set.get(self.set.prev(self.handle?)?)
+    }
+
+    #[inline]
+    pub fn move_next(&mut self) {
+        if let Some(handle) = self.handle {
+            self.handle = self.set.next(handle);
+        }
+    }
+
+    #[inline]
+    pub fn move_prev(&mut self) {
+        if let Some(handle) = self.handle {
+            self.handle = self.set.prev(handle);
+        }
+    }
+}
+
+/// A `Cursor` that can also modify, remove and insert values around its position.
+pub struct CursorMut<'a, K, V, C> {
+    set: &'a mut C,
+    handle: Option<Handle>,
+    phantom_data: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, C: SetCollection<K, V>> CursorMut<'a, K, V, C> {
+    #[inline]
+    pub fn new(set: &'a mut C, handle: Handle) -> Self {
+        let handle = set.get(handle).map(|_| handle);
+        Self {
+            set,
+            handle,
+            phantom_data: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn handle(&self) -> Option<Handle> {
+        self.handle
+    }
+
+    #[inline]
+    pub fn current(&self) -> Option<&V> {
+        self.set.get(self.handle?)
+    }
+
+    /// The key of the value must not be changed.
+    #[inline]
+    pub fn current_mut(&mut self) -> Option<C::ValueMut<'_>> {
+        self.set.get_mut(self.handle?)
+    }
+
+    #[inline]
+    pub fn peek_next(&self) -> Option<&V> {
+        self.set.get(self.set.next(self.handle?)?)
+    }
+
+    #[inline]
+    pub fn peek_prev(&self) -> Option<&V> {
+        self.set.get(self.set.prev(self.handle?)?)
+    }
+
+    #[inline]
+    pub fn move_next(&mut self) {
+        if let Some(handle) = self.handle {
+            self.handle = self.set.next(handle);
+        }
+    }
+
+    #[inline]
+    pub fn move_prev(&mut self) {
+        if let Some(handle) = self.handle {
+            self.handle = self.set.prev(handle);
+        }
+    }
+
+    /// Removes the current value and moves the cursor to the next one.
+    #[inline]
+    pub fn remove_current(&mut self) -> Option<V> {
+        let handle = self.handle?;
+        let prev = self.set.prev(handle);
+        let val = self.set.remove(handle)?;
+        self.handle = match prev {
+            Some(prev) => self.set.index_after(prev.index()),
+            None => self.set.first_handle(),
+        };
+        Some(val)
+    }
+
+    /// Inserts `val` right before the current value, the cursor stays on the current value.
+    /// Returns false if `val` does not belong there.
+    #[inline]
+    pub fn insert_before(&mut self, val: V) -> bool {
+        let Some(handle) = self.handle else {
+            return false;
+        };
+        let Some(index) = self.set.insert_before(handle, val) else {
+            return false;
+        };
+        self.handle = self.set.index_after(index);
+        true
+    }
+
+    /// Inserts `val` right after the current value, the cursor stays on the current value.
+    /// Returns false if `val` does not belong there.
+    #[inline]
+    pub fn insert_after(&mut self, val: V) -> bool {
+        let Some(handle) = self.handle else {
+            return false;
+        };
+        let Some(index) = self.set.insert_after(handle, val) else {
+            return false;
+        };
+        self.handle = self.set.index_before(index);
+        true
+    }
+}
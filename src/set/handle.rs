@@ -0,0 +1,21 @@
+/// A reference to a value in a set collection.
+///
+/// A handle remembers the generation of its slot, so it stops resolving once the value is
+/// removed, even if the slot is reused by a later insert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle {
+    pub(super) index: u32,
+    pub(super) generation: u32,
+}
+
+impl Handle {
+    #[inline(always)]
+    pub(super) fn new(index: u32, generation: u32) -> Self {
+        Self { index, generation }
+    }
+
+    #[inline(always)]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
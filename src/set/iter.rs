@@ -0,0 +1,372 @@
+use crate::EMPTY_REF;
+use crate::id_index::IdIndex;
+use crate::set::list::SetList;
+use crate::set::node::Node;
+use crate::set::sort::{KeyValue, SetCollection};
+use crate::set::summary::Summary;
+use crate::set::tree::SetTree;
+use crate::set::value_mut::IterValueMut;
+use alloc::vec;
+use core::marker::PhantomData;
+use core::ops::{Bound, RangeBounds};
+use core::ptr;
+use core::slice;
+
+pub struct TreeIterator<'a, K, V, S = ()> {
+    tree: &'a SetTree<K, V, S>,
+    front: u32,
+    back: u32,
+}
+
+pub struct TreeIteratorMut<'a, K, V, S = ()> {
+    // links are read through the raw buffer, a shared borrow of the tree would also cover
+    // the values already handed out as `&mut V`
+    nodes: *mut Node<V, S>,
+    len: usize,
+    // the yielded guards write id changes through it, so the iterator is not `Send`
+    ids: *mut IdIndex<V>,
+    front: u32,
+    back: u32,
+    phantom_data: PhantomData<&'a mut SetTree<K, V, S>>,
+}
+
+unsafe impl<K: Sync, V: Sync, S: Sync> Sync for TreeIteratorMut<'_, K, V, S> {}
+
+pub struct TreeIntoIterator<K, V, S = ()> {
+    tree: SetTree<K, V, S>,
+    front: u32,
+    back: u32,
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default> SetTree<K, V> {
+    /// Keys must not be changed through the returned guards. Only trees without a summary
+    /// have it, a summarized tree uses `get_mut` which keeps the summaries up to date.
+    #[inline]
+    pub fn iter_mut(&mut self) -> TreeIteratorMut<'_, K, V> {
+        let (front, back) = self.full_range();
+        TreeIteratorMut {
+            nodes: self.store.buffer.as_mut_ptr(),
+            len: self.store.buffer.len(),
+            ids: self.ids.as_mut().map_or(ptr::null_mut(), ptr::from_mut),
+            front,
+            back,
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> SetTree<K, V, S> {
+    #[inline]
+    pub fn iter(&self) -> TreeIterator<'_, K, V, S> {
+        let (front, back) = self.full_range();
+        TreeIterator {
+            tree: self,
+            front,
+            back,
+        }
+    }
+
+    #[inline]
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> TreeIterator<'_, K, V, S> {
+        let front = match range.start_bound() {
+            Bound::Included(key) => self.first_index_greater_or_equal(key),
+            Bound::Excluded(key) => self.first_index_greater(key),
+            Bound::Unbounded => self.full_range().0,
+        };
+        let back = match range.end_bound() {
+            Bound::Included(key) => self.first_index_less(key),
+            Bound::Excluded(key) => self.first_index_less_strict(key),
+            Bound::Unbounded => self.full_range().1,
+        };
+
+        let is_empty = front == EMPTY_REF
+            || back == EMPTY_REF
+            || self.node(front).value.key() > self.node(back).value.key();
+
+        if is_empty {
+            TreeIterator {
+                tree: self,
+                front: EMPTY_REF,
+                back: EMPTY_REF,
+            }
+        } else {
+            TreeIterator {
+                tree: self,
+                front,
+                back,
+            }
+        }
+    }
+
+    #[inline]
+    fn full_range(&self) -> (u32, u32) {
+        let front = self.first_handle().map_or(EMPTY_REF, |h| h.index());
+        let back = self.last_handle().map_or(EMPTY_REF, |h| h.index());
+        (front, back)
+    }
+}
+
+// the iterator is done when the front passes the back
+#[inline]
+fn step_front<F: Fn(u32) -> u32>(front: &mut u32, back: &mut u32, next_index: F) -> u32 {
+    let index = *front;
+    if index == *back {
+        *front = EMPTY_REF;
+        *back = EMPTY_REF;
+    } else if index != EMPTY_REF {
+        *front = next_index(index);
+    }
+    index
+}
+
+#[inline]
+fn step_back<F: Fn(u32) -> u32>(front: &mut u32, back: &mut u32, prev_index: F) -> u32 {
+    let index = *back;
+    if index == *front {
+        *front = EMPTY_REF;
+        *back = EMPTY_REF;
+    } else if index != EMPTY_REF {
+        *back = prev_index(index);
+    }
+    index
+}
+
+impl<'a, K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> Iterator for TreeIterator<'a, K, V, S> {
+    type Item = &'a V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let tree = self.tree;
+        let index = step_front(&mut self.front, &mut self.back, |i| tree.next_index(i));
+        if index == EMPTY_REF {
+            return None;
+        }
+        Some(&tree.node(index).value)
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> DoubleEndedIterator
+    for TreeIterator<'_, K, V, S>
+{
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let tree = self.tree;
+        let index = step_back(&mut self.front, &mut self.back, |i| tree.prev_index(i));
+        if index == EMPTY_REF {
+            return None;
+        }
+        Some(&tree.node(index).value)
+    }
+}
+
+// only the link fields are read, the values may be borrowed mutably elsewhere
+#[inline]
+unsafe fn raw_next_index<V, S>(nodes: *const Node<V, S>, mut index: u32) -> u32 {
+    unsafe {
+        let right = (*nodes.add(index as usize)).right;
+        if right != EMPTY_REF {
+            index = right;
+            loop {
+                let left = (*nodes.add(index as usize)).left;
+                if left == EMPTY_REF {
+                    return index;
+                }
+                index = left;
+            }
+        }
+        let mut parent_index = (*nodes.add(index as usize)).parent;
+        while parent_index != EMPTY_REF && (*nodes.add(parent_index as usize)).right == index {
+            index = parent_index;
+            parent_index = (*nodes.add(parent_index as usize)).parent;
+        }
+        parent_index
+    }
+}
+
+#[inline]
+unsafe fn raw_prev_index<V, S>(nodes: *const Node<V, S>, mut index: u32) -> u32 {
+    unsafe {
+        let left = (*nodes.add(index as usize)).left;
+        if left != EMPTY_REF {
+            index = left;
+            loop {
+                let right = (*nodes.add(index as usize)).right;
+                if right == EMPTY_REF {
+                    return index;
+                }
+                index = right;
+            }
+        }
+        let mut parent_index = (*nodes.add(index as usize)).parent;
+        while parent_index != EMPTY_REF && (*nodes.add(parent_index as usize)).left == index {
+            index = parent_index;
+            parent_index = (*nodes.add(parent_index as usize)).parent;
+        }
+        parent_index
+    }
+}
+
+impl<'a, K, V, S> TreeIteratorMut<'a, K, V, S> {
+    #[inline]
+    fn value(&mut self, index: u32) -> IterValueMut<'a, V> {
+        debug_assert!((index as usize) < self.len);
+        // every node is visited only once, so no two references to the same value exist
+        let value = unsafe { &mut (*self.nodes.add(index as usize)).value };
+        IterValueMut::new(value, self.ids, index)
+    }
+}
+
+impl<'a, K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> Iterator for TreeIteratorMut<'a, K, V, S> {
+    type Item = IterValueMut<'a, V>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let nodes = self.nodes;
+        let index = step_front(&mut self.front, &mut self.back, |i| unsafe {
+            raw_next_index(nodes, i)
+        });
+        if index == EMPTY_REF {
+            return None;
+        }
+        Some(self.value(index))
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> DoubleEndedIterator
+    for TreeIteratorMut<'_, K, V, S>
+{
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let nodes = self.nodes;
+        let index = step_back(&mut self.front, &mut self.back, |i| unsafe {
+            raw_prev_index(nodes, i)
+        });
+        if index == EMPTY_REF {
+            return None;
+        }
+        Some(self.value(index))
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> Iterator for TreeIntoIterator<K, V, S> {
+    type Item = V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = step_front(&mut self.front, &mut self.back, |i| self.tree.next_index(i));
+        if index == EMPTY_REF {
+            return None;
+        }
+        Some(core::mem::take(&mut self.tree.node_mut(index).value))
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> DoubleEndedIterator
+    for TreeIntoIterator<K, V, S>
+{
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let index = step_back(&mut self.front, &mut self.back, |i| self.tree.prev_index(i));
+        if index == EMPTY_REF {
+            return None;
+        }
+        Some(core::mem::take(&mut self.tree.node_mut(index).value))
+    }
+}
+
+impl<'a, K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> IntoIterator for &'a SetTree<K, V, S> {
+    type Item = &'a V;
+    type IntoIter = TreeIterator<'a, K, V, S>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Ord, V: KeyValue<K> + Clone + Default> IntoIterator for &'a mut SetTree<K, V> {
+    type Item = IterValueMut<'a, V>;
+    type IntoIter = TreeIteratorMut<'a, K, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> IntoIterator for SetTree<K, V, S> {
+    type Item = V;
+    type IntoIter = TreeIntoIterator<K, V, S>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        let (front, back) = self.full_range();
+        TreeIntoIterator {
+            tree: self,
+            front,
+            back,
+        }
+    }
+}
+
+impl<V> SetList<V> {
+    #[inline]
+    pub fn iter(&self) -> slice::Iter<'_, V> {
+        self.buffer.iter()
+    }
+
+    /// Keys must not be changed through the returned references.
+    #[inline]
+    pub fn iter_mut(&mut self) -> slice::IterMut<'_, V> {
+        self.buffer.iter_mut()
+    }
+
+    #[inline]
+    pub fn range<K: Ord, R: RangeBounds<K>>(&self, range: R) -> slice::Iter<'_, V>
+    where
+        V: KeyValue<K>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(key) => self.buffer.partition_point(|v| v.key() < key),
+            Bound::Excluded(key) => self.buffer.partition_point(|v| v.key() <= key),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => self.buffer.partition_point(|v| v.key() <= key),
+            Bound::Excluded(key) => self.buffer.partition_point(|v| v.key() < key),
+            Bound::Unbounded => self.buffer.len(),
+        };
+
+        self.buffer[start..end.max(start)].iter()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a SetList<V> {
+    type Item = &'a V;
+    type IntoIter = slice::Iter<'a, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.buffer.iter()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a mut SetList<V> {
+    type Item = &'a mut V;
+    type IntoIter = slice::IterMut<'a, V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.buffer.iter_mut()
+    }
+}
+
+impl<V> IntoIterator for SetList<V> {
+    type Item = V;
+    type IntoIter = vec::IntoIter<V>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.buffer.into_iter()
+    }
+}
@@ -0,0 +1,505 @@
+use crate::EMPTY_REF;
+use crate::set::node::Color;
+use crate::set::sort::KeyValue;
+use crate::set::summary::Summary;
+use crate::set::tree::SetTree;
+use alloc::vec::Vec;
+
+// A subtree is passed around as (root, black height). Its nodes live in the same pool and
+// its root may have a stale parent until it is joined.
+pub(super) type Part = (u32, u32);
+
+const EMPTY_PART: Part = (EMPTY_REF, 0);
+
+impl<K: Ord, V: Clone + Default + KeyValue<K>, S: Summary<V>> SetTree<K, V, S> {
+    /// Moves all values with a key greater than or equal to `key` into a new tree.
+    ///
+    /// The moved values get new indices in the returned tree, the rest keep theirs.
+    pub fn split_off(&mut self, key: &K) -> Self {
+        if self.root == EMPTY_REF {
+            let mut other = Self::with_summary(8);
+            other.ids = self.ids.as_ref().map(|ids| ids.empty_like());
+            return other;
+        }
+
+        let all = (self.root, self.black_height(self.root));
+        let (left, right) = self.split_by(all, &|k: &K| k < key);
+        self.set_root(left.0);
+
+        // the nil node takes one slot of the new pool
+        let mut other = Self::with_summary(self.subtree_len(right.0) + 1);
+        other.ids = self.ids.as_ref().map(|ids| ids.empty_like());
+        let root = other.move_subtree(self, right.0);
+        other.set_root(root);
+
+        other
+    }
+
+    /// Moves all values of `other` into this tree and leaves `other` empty.
+    ///
+    /// If the key ranges do not overlap the nodes of `other` are moved in O(k) and the trees
+    /// are joined in O(log n). Otherwise every value of `other` is inserted one by one, which
+    /// costs O(k log n) for `k` values in `other`.
+    pub fn append(&mut self, other: &mut Self) {
+        if other.root == EMPTY_REF {
+            return;
+        }
+        if self.root == EMPTY_REF {
+            let root = self.move_subtree(other, other.root);
+            self.set_root(root);
+            return;
+        }
+
+        let min = self.find_left_minimum(self.root);
+        let max = self.find_right_minimum(self.root);
+        let other_min = other.find_left_minimum(other.root);
+        let other_max = other.find_right_minimum(other.root);
+
+        let is_after = self.node(max).value.key() < other.node(other_min).value.key();
+        let is_before = other.node(other_max).value.key() < self.node(min).value.key();
+
+        if is_after || is_before {
+            let this = (self.root, self.black_height(self.root));
+            let moved = self.move_subtree(other, other.root);
+            let moved = (moved, self.black_height(moved));
+            if is_after {
+                self.join_two(this, moved);
+            } else {
+                self.join_two(moved, this);
+            }
+        } else {
+            let root = other.root;
+            other.set_root(EMPTY_REF);
+            if let Some(ids) = &mut other.ids {
+                ids.clear();
+            }
+            let mut index = other.find_left_minimum(root);
+            while index != EMPTY_REF {
+                let next = other.next_index(index);
+                let value = core::mem::take(&mut other.node_mut(index).value);
+                self.insert_value(value);
+                index = next;
+            }
+            other.put_back_subtree(root);
+        }
+    }
+
+    // copies a subtree of `other` with the same shape into this pool and frees it in `other`
+    fn move_subtree(&mut self, other: &mut Self, root: u32) -> u32 {
+        if root == EMPTY_REF {
+            return EMPTY_REF;
+        }
+        if other.root == root {
+            other.root = EMPTY_REF;
+        }
+
+        let new_root = self.store.get_free_index();
+        let mut stack = Vec::with_capacity(64);
+        stack.push((root, new_root));
+
+        while let Some((index, new_index)) = stack.pop() {
+            let node = other.node(index);
+            let (left, right, color) = (node.left, node.right, node.color);
+            let summary = node.summary.clone();
+            if let Some(ids) = &mut other.ids {
+                ids.remove(&other.store.buffer[index as usize].value);
+            }
+            let value = core::mem::take(&mut other.node_mut(index).value);
+            other.store.put_back(index);
+
+            let new_left = self.new_child(left, new_index, &mut stack);
+            let new_right = self.new_child(right, new_index, &mut stack);
+
+            let node = self.node_mut(new_index);
+            node.left = new_left;
+            node.right = new_right;
+            node.color = color;
+            node.value = value;
+            node.summary = summary;
+            self.register_id(new_index);
+        }
+        self.node_mut(new_root).parent = EMPTY_REF;
+
+        new_root
+    }
+
+    fn subtree_len(&self, root: u32) -> usize {
+        if root == EMPTY_REF {
+            return 0;
+        }
+        let mut len = 0;
+        let mut stack = Vec::with_capacity(64);
+        stack.push(root);
+        while let Some(index) = stack.pop() {
+            len += 1;
+            let node = self.node(index);
+            if node.left != EMPTY_REF {
+                stack.push(node.left);
+            }
+            if node.right != EMPTY_REF {
+                stack.push(node.right);
+            }
+        }
+        len
+    }
+
+    #[inline]
+    fn new_child(&mut self, index: u32, parent: u32, stack: &mut Vec<(u32, u32)>) -> u32 {
+        if index == EMPTY_REF {
+            return EMPTY_REF;
+        }
+        let new_index = self.store.get_free_index();
+        self.node_mut(new_index).parent = parent;
+        stack.push((index, new_index));
+        new_index
+    }
+
+    #[inline]
+    pub(super) fn black_height(&self, mut index: u32) -> u32 {
+        let mut height = 0;
+        while index != EMPTY_REF {
+            let node = self.node(index);
+            if node.color == Color::Black {
+                height += 1;
+            }
+            index = node.left;
+        }
+        height
+    }
+
+    #[inline]
+    pub(super) fn set_root(&mut self, root: u32) {
+        self.root = root;
+        if root != EMPTY_REF {
+            self.node_mut(root).parent = EMPTY_REF;
+        }
+    }
+
+    /// Joins `left`, `mid` and `right` into one tree and makes it the root. All keys of `left`
+    /// must be less than the key of `mid` and all keys of `right` greater.
+    pub(super) fn join(&mut self, left: Part, mid: u32, right: Part) -> Part {
+        let (left, mut lh) = left;
+        let (right, mut rh) = right;
+        if !self.is_black(left) {
+            self.node_mut(left).color = Color::Black;
+            lh += 1;
+        }
+        if !self.is_black(right) {
+            self.node_mut(right).color = Color::Black;
+            rh += 1;
+        }
+
+        if lh == rh {
+            let node = self.node_mut(mid);
+            node.parent = EMPTY_REF;
+            node.left = left;
+            node.right = right;
+            node.color = Color::Black;
+            self.set_parent(left, mid);
+            self.set_parent(right, mid);
+            self.update_summary(mid);
+            self.root = mid;
+            return (mid, lh + 1);
+        }
+
+        let height = lh.max(rh);
+        if lh > rh {
+            // go down the right spine of left to a black node with the height of right
+            self.set_root(left);
+            let mut parent = EMPTY_REF;
+            let mut index = left;
+            let mut h = lh;
+            while index != EMPTY_REF && !(self.is_black(index) && h <= rh) {
+                if self.is_black(index) {
+                    h -= 1;
+                }
+                parent = index;
+                index = self.node(index).right;
+            }
+            self.attach_red(mid, parent, index, right);
+            self.node_mut(parent).right = mid;
+        } else {
+            self.set_root(right);
+            let mut parent = EMPTY_REF;
+            let mut index = right;
+            let mut h = rh;
+            while index != EMPTY_REF && !(self.is_black(index) && h <= lh) {
+                if self.is_black(index) {
+                    h -= 1;
+                }
+                parent = index;
+                index = self.node(index).left;
+            }
+            self.attach_red(mid, parent, left, index);
+            self.node_mut(parent).left = mid;
+        }
+
+        self.update_summary_to_root(mid);
+        let parent = self.node(mid).parent;
+        if self.node(parent).color == Color::Red {
+            self.fix_red_black_properties_after_insert(mid, parent);
+        }
+
+        let root = self.root;
+        if self.is_black(root) {
+            (root, height)
+        } else {
+            self.node_mut(root).color = Color::Black;
+            (root, height + 1)
+        }
+    }
+
+    /// Same as `join` but without a middle node.
+    pub(super) fn join_two(&mut self, left: Part, right: Part) -> Part {
+        if right.0 == EMPTY_REF {
+            self.set_root(left.0);
+            return left;
+        }
+        if left.0 == EMPTY_REF {
+            self.set_root(right.0);
+            return right;
+        }
+
+        self.set_root(right.0);
+        let mid = self.find_left_minimum(right.0);
+        self.unlink_index(mid);
+        let right = self.root;
+        let rh = self.black_height(right);
+
+        self.join(left, mid, (right, rh))
+    }
+
+    /// Splits a subtree into the nodes where `is_left` holds and the rest. `is_left` must be
+    /// true for a prefix of the keys.
+    pub(super) fn split_by<F>(&mut self, part: Part, is_left: &F) -> (Part, Part)
+    where
+        F: Fn(&K) -> bool,
+    {
+        let (index, height) = part;
+        if index == EMPTY_REF {
+            return (EMPTY_PART, EMPTY_PART);
+        }
+        let node = self.node(index);
+        let left = node.left;
+        let right = node.right;
+        let child_height = if node.color == Color::Black {
+            height - 1
+        } else {
+            height
+        };
+
+        if is_left(node.value.key()) {
+            let (l, r) = self.split_by((right, child_height), is_left);
+            (self.join((left, child_height), index, l), r)
+        } else {
+            let (l, r) = self.split_by((left, child_height), is_left);
+            (l, self.join(r, index, (right, child_height)))
+        }
+    }
+
+    /// Returns all nodes of a subtree to the pool.
+    pub(super) fn put_back_subtree(&mut self, root: u32) {
+        if root == EMPTY_REF {
+            return;
+        }
+        self.put_back_node(root);
+
+        let mut n = 1;
+        while n > 0 {
+            let i0 = self.store.unused.len() - n;
+            n = 0;
+            for i in i0..self.store.unused.len() {
+                let index = self.store.unused[i];
+                let node = self.node(index);
+                let left = node.left;
+                let right = node.right;
+                if left != EMPTY_REF {
+                    self.put_back_node(left);
+                    n += 1;
+                }
+                if right != EMPTY_REF {
+                    self.put_back_node(right);
+                    n += 1;
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn put_back_node(&mut self, index: u32) {
+        if let Some(ids) = &mut self.ids {
+            let node = unsafe { self.store.buffer.get_unchecked(index as usize) };
+            ids.remove(&node.value);
+        }
+        self.store.put_back(index);
+    }
+
+    #[inline]
+    fn attach_red(&mut self, mid: u32, parent: u32, left: u32, right: u32) {
+        let node = self.node_mut(mid);
+        node.parent = parent;
+        node.left = left;
+        node.right = right;
+        node.color = Color::Red;
+        self.set_parent(left, mid);
+        self.set_parent(right, mid);
+    }
+
+    #[inline(always)]
+    fn set_parent(&mut self, index: u32, parent: u32) {
+        if index != EMPTY_REF {
+            self.node_mut(index).parent = parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::EMPTY_REF;
+    use crate::set::node::Color;
+    use crate::set::sort::SetCollection;
+    use crate::set::tree::SetTree;
+    use alloc::vec::Vec;
+
+    // returns the black height and checks links, order and the red rule, equal keys are allowed
+    fn validate(tree: &SetTree<i32, i32>, index: u32, parent: u32) -> u32 {
+        if index == EMPTY_REF {
+            return 0;
+        }
+        let node = tree.node(index);
+        assert_eq!(node.parent, parent);
+        if node.color == Color::Red {
+            assert!(tree.is_black(node.left) && tree.is_black(node.right));
+        }
+        if node.left != EMPTY_REF {
+            assert!(tree.node(node.left).value <= node.value);
+        }
+        if node.right != EMPTY_REF {
+            assert!(tree.node(node.right).value >= node.value);
+        }
+        let lh = validate(tree, node.left, index);
+        let rh = validate(tree, node.right, index);
+        assert_eq!(lh, rh);
+        lh + if node.color == Color::Black { 1 } else { 0 }
+    }
+
+    #[test]
+    fn test_split_join_00() {
+        for n in 0..64 {
+            for at in 0..=n {
+                let mut tree = SetTree::new(8);
+                for i in 0..n {
+                    tree.insert(i);
+                }
+                let all = (tree.root, tree.black_height(tree.root));
+                let (left, right) = tree.split_by(all, &|k: &i32| *k < at);
+                tree.set_root(left.0);
+                assert_eq!(validate(&tree, left.0, EMPTY_REF), left.1);
+                tree.set_root(right.0);
+                assert_eq!(validate(&tree, right.0, EMPTY_REF), right.1);
+
+                tree.join_two(left, right);
+                validate(&tree, tree.root, EMPTY_REF);
+                let values: Vec<i32> = tree.iter().copied().collect();
+                assert_eq!(values, (0..n).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn test_remove_range_00() {
+        for n in 0..40 {
+            for a in 0..n {
+                for b in a..n {
+                    let mut tree = SetTree::new(8);
+                    for i in 0..n {
+                        tree.insert(i);
+                    }
+                    tree.remove_range(a..b);
+                    validate(&tree, tree.root, EMPTY_REF);
+                    let values: Vec<i32> = tree.iter().copied().collect();
+                    let template: Vec<i32> = (0..n).filter(|i| !(a..b).contains(i)).collect();
+                    assert_eq!(values, template);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_off_append_00() {
+        for n in 0..48 {
+            for at in 0..=n {
+                let mut tree = SetTree::new(8);
+                for i in 0..n {
+                    tree.insert(i);
+                }
+                let mut right = tree.split_off(&at);
+                validate(&tree, tree.root, EMPTY_REF);
+                validate(&right, right.root, EMPTY_REF);
+                assert!(tree.iter().copied().eq(0..at));
+                assert!(right.iter().copied().eq(at..n));
+                // the pool of the new tree fits the moved values exactly
+                assert_eq!(right.store.buffer.len(), (n - at + 1).max(8) as usize);
+
+                if at % 2 == 0 {
+                    tree.append(&mut right);
+                    validate(&tree, tree.root, EMPTY_REF);
+                    assert!(tree.iter().copied().eq(0..n));
+                } else {
+                    right.append(&mut tree);
+                    validate(&right, right.root, EMPTY_REF);
+                    assert!(right.iter().copied().eq(0..n));
+                }
+                assert!(tree.is_empty() || right.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_sorted_00() {
+        for n in 0..100 {
+            let mut tree = SetTree::from_sorted(0..n);
+            validate(&tree, tree.root, EMPTY_REF);
+            assert!(tree.iter().copied().eq(0..n));
+            tree.insert(n);
+            tree.delete(&0);
+            validate(&tree, tree.root, EMPTY_REF);
+        }
+    }
+
+    #[test]
+    fn test_update_key_00() {
+        for n in 1..40 {
+            for from in 0..n {
+                for to in [-1, 2 * from - 2, 2 * from + 1, 2 * from + 2, 2 * n - 1, 2 * n + 1] {
+                    let mut tree = SetTree::new(8);
+                    let mut indices = Vec::new();
+                    for i in 0..n {
+                        indices.push(tree.insert(2 * i));
+                    }
+                    let index = indices[from as usize];
+                    let handle = tree.handle(index).unwrap();
+                    let (prev, next) = (tree.prev(handle), tree.next(handle));
+                    assert!(tree.update_key(handle, |v| *v = to));
+                    if to == 2 * from - 2 || to == 2 * from + 2 {
+                        // a key equal to a neighbor keeps the node in place
+                        assert_eq!((tree.prev(handle), tree.next(handle)), (prev, next));
+                    }
+                    validate(&tree, tree.root, EMPTY_REF);
+
+                    let mut template: Vec<i32> = (0..n).map(|i| 2 * i).filter(|&v| v != 2 * from).collect();
+                    template.push(to);
+                    template.sort_unstable();
+                    assert!(tree.iter().copied().eq(template.iter().copied()));
+                    assert_eq!(tree.get(handle), Some(&to));
+
+                    // a stale handle is rejected, even after its slot was reused
+                    tree.remove(handle);
+                    tree.insert(to);
+                    assert!(!tree.update_key(handle, |v| *v = 2 * n + 3));
+                    assert!(tree.iter().copied().eq(template.iter().copied()));
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,17 @@
 use crate::EMPTY_REF;
+use crate::set::handle::Handle;
 use crate::set::sort::{KeyValue, SetCollection};
 use alloc::vec::Vec;
 use core::cmp::Ordering;
+use core::ops::{Bound, RangeBounds};
 
+/// A set kept in a sorted `Vec`.
+///
+/// Positions shift on every insert or delete, so all handles of a list share one generation:
+/// any change of the list invalidates every handle taken before it.
 pub struct SetList<V> {
     pub(super) buffer: Vec<V>,
+    pub(super) generation: u32,
 }
 
 impl<V> SetList<V> {
@@ -12,35 +19,115 @@ impl<V> SetList<V> {
     pub fn new(capacity: usize) -> Self {
         Self {
             buffer: Vec::with_capacity(capacity),
+            generation: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub(super) fn bump_generation(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    #[inline(always)]
+    fn to_index(&self, index: usize) -> u32 {
+        if index < self.buffer.len() {
+            index as u32
+        } else {
+            EMPTY_REF
         }
     }
 }
 
 impl<K: Ord + Copy, V: KeyValue<K>> SetCollection<K, V> for SetList<V> {
+    type ValueMut<'a>
+        = &'a mut V
+    where
+        Self: 'a;
+
     #[inline]
     fn is_empty(&self) -> bool {
         self.buffer.is_empty()
     }
 
     #[inline]
-    fn insert(&mut self, val: V) {
+    fn insert(&mut self, val: V) -> u32 {
         let index = self
             .buffer
             .binary_search_by_key(&val.key(), |v| v.key())
             .unwrap_or_else(|index| index);
         self.buffer.insert(index, val);
+        self.bump_generation();
+        index as u32
     }
 
     #[inline]
     fn delete(&mut self, key: &K) {
         if let Ok(index) = self.buffer.binary_search_by_key(key, |v| *v.key()) {
             self.buffer.remove(index);
+            self.bump_generation();
         }
     }
 
     #[inline]
     fn delete_by_index(&mut self, index: u32) {
         self.buffer.remove(index as usize);
+        self.bump_generation();
+    }
+
+    #[inline]
+    fn take(&mut self, key: &K) -> Option<V> {
+        let index = self.buffer.binary_search_by_key(key, |v| *v.key()).ok()?;
+        self.bump_generation();
+        Some(self.buffer.remove(index))
+    }
+
+    #[inline]
+    fn pop_first(&mut self) -> Option<V> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        self.bump_generation();
+        Some(self.buffer.remove(0))
+    }
+
+    #[inline]
+    fn pop_last(&mut self) -> Option<V> {
+        let val = self.buffer.pop()?;
+        self.bump_generation();
+        Some(val)
+    }
+
+    #[inline]
+    fn retain<P>(&mut self, pred: P)
+    where
+        P: FnMut(&V) -> bool,
+    {
+        let len = self.buffer.len();
+        self.buffer.retain(pred);
+        if self.buffer.len() != len {
+            self.bump_generation();
+        }
+    }
+
+    #[inline]
+    fn remove_range<R>(&mut self, range: R)
+    where
+        R: RangeBounds<K>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(key) => self.buffer.partition_point(|v| v.key() < key),
+            Bound::Excluded(key) => self.buffer.partition_point(|v| v.key() <= key),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => self.buffer.partition_point(|v| v.key() <= key),
+            Bound::Excluded(key) => self.buffer.partition_point(|v| v.key() < key),
+            Bound::Unbounded => self.buffer.len(),
+        };
+        if start < end {
+            self.buffer.drain(start..end);
+            self.bump_generation();
+        }
     }
 
     #[inline]
@@ -53,43 +140,178 @@ impl<K: Ord + Copy, V: KeyValue<K>> SetCollection<K, V> for SetList<V> {
     }
 
     #[inline]
-    fn index_after(&self, index: u32) -> u32 {
-        index + 1
+    fn index_after(&self, index: u32) -> Option<Handle> {
+        self.handle(index.checked_add(1)?)
     }
 
-    fn index_before(&self, index: u32) -> u32 {
-        index - 1
+    #[inline]
+    fn index_before(&self, index: u32) -> Option<Handle> {
+        self.handle(index.checked_sub(1)?)
     }
 
     #[inline]
-    fn first_index_less(&self, key: &K) -> u32 {
-        match self.buffer.binary_search_by(|e| e.key().cmp(key)) {
-            Ok(index) => index as u32,
-            Err(index) => {
-                if index > 0 {
-                    (index - 1) as u32
-                } else {
-                    EMPTY_REF
-                }
-            }
+    fn handle(&self, index: u32) -> Option<Handle> {
+        if (index as usize) < self.buffer.len() {
+            Some(Handle::new(index, self.generation))
+        } else {
+            None
         }
     }
 
+    #[inline]
+    fn get(&self, handle: Handle) -> Option<&V> {
+        if handle.generation == self.generation {
+            self.buffer.get(handle.index as usize)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn get_mut(&mut self, handle: Handle) -> Option<&mut V> {
+        if handle.generation == self.generation {
+            self.buffer.get_mut(handle.index as usize)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn next(&self, handle: Handle) -> Option<Handle> {
+        self.get(handle)?;
+        self.index_after(handle.index)
+    }
+
+    #[inline]
+    fn prev(&self, handle: Handle) -> Option<Handle> {
+        self.get(handle)?;
+        self.index_before(handle.index)
+    }
+
+    #[inline]
+    fn first_handle(&self) -> Option<Handle> {
+        self.handle(0)
+    }
+
+    #[inline]
+    fn last_handle(&self) -> Option<Handle> {
+        self.handle((self.buffer.len() as u32).checked_sub(1)?)
+    }
+
+    #[inline]
+    fn remove(&mut self, handle: Handle) -> Option<V> {
+        self.get(handle)?;
+        let val = self.buffer.remove(handle.index as usize);
+        self.bump_generation();
+        Some(val)
+    }
+
+    #[inline]
+    fn insert_before(&mut self, handle: Handle, val: V) -> Option<u32> {
+        let index = handle.index as usize;
+        if val.key() > self.get(handle)?.key() {
+            return None;
+        }
+        if index > 0 && self.buffer[index - 1].key() > val.key() {
+            return None;
+        }
+        self.buffer.insert(index, val);
+        self.bump_generation();
+        Some(index as u32)
+    }
+
+    #[inline]
+    fn insert_after(&mut self, handle: Handle, val: V) -> Option<u32> {
+        let index = handle.index as usize + 1;
+        if val.key() < self.get(handle)?.key() {
+            return None;
+        }
+        if index < self.buffer.len() && self.buffer[index].key() < val.key() {
+            return None;
+        }
+        self.buffer.insert(index, val);
+        self.bump_generation();
+        Some(index as u32)
+    }
+
+    #[inline]
+    fn first_index_less(&self, key: &K) -> u32 {
+        self.first_index_less_by(|k| k.cmp(key))
+    }
+
     #[inline]
     fn first_index_less_by<F>(&self, f: F) -> u32
     where
         F: Fn(&K) -> Ordering,
     {
-        match self.buffer.binary_search_by(|v| f(v.key())) {
-            Ok(index) => index as u32,
-            Err(index) => {
-                if index > 0 {
-                    (index - 1) as u32
-                } else {
-                    EMPTY_REF
-                }
-            }
-        }
+        let index = self.buffer.partition_point(|v| f(v.key()) != Ordering::Greater);
+        if index > 0 { (index - 1) as u32 } else { EMPTY_REF }
+    }
+
+    #[inline]
+    fn first_index_less_strict(&self, key: &K) -> u32 {
+        self.first_index_less_strict_by(|k| k.cmp(key))
+    }
+
+    #[inline]
+    fn first_index_less_strict_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering,
+    {
+        let index = self.buffer.partition_point(|v| f(v.key()) == Ordering::Less);
+        if index > 0 { (index - 1) as u32 } else { EMPTY_REF }
+    }
+
+    #[inline]
+    fn first_index_greater(&self, key: &K) -> u32 {
+        self.first_index_greater_by(|k| k.cmp(key))
+    }
+
+    #[inline]
+    fn first_index_greater_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering,
+    {
+        let index = self.buffer.partition_point(|v| f(v.key()) != Ordering::Greater);
+        self.to_index(index)
+    }
+
+    #[inline]
+    fn first_index_greater_or_equal(&self, key: &K) -> u32 {
+        self.first_index_greater_or_equal_by(|k| k.cmp(key))
+    }
+
+    #[inline]
+    fn first_index_greater_or_equal_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering,
+    {
+        let index = self.buffer.partition_point(|v| f(v.key()) == Ordering::Less);
+        self.to_index(index)
+    }
+
+    #[inline]
+    fn first_less_where<P>(&self, key: &K, pred: P) -> u32
+    where
+        P: Fn(&V) -> bool,
+    {
+        let index = self.buffer.partition_point(|v| v.key() < key);
+        self.buffer[..index]
+            .iter()
+            .rposition(pred)
+            .map_or(EMPTY_REF, |i| i as u32)
+    }
+
+    #[inline]
+    fn first_greater_where<P>(&self, key: &K, pred: P) -> u32
+    where
+        P: Fn(&V) -> bool,
+    {
+        let index = self.buffer.partition_point(|v| v.key() <= key);
+        self.buffer[index..]
+            .iter()
+            .position(pred)
+            .map_or(EMPTY_REF, |i| (index + i) as u32)
     }
 
     #[inline]
@@ -105,5 +327,6 @@ impl<K: Ord + Copy, V: KeyValue<K>> SetCollection<K, V> for SetList<V> {
     #[inline]
     fn clear(&mut self) {
         self.buffer.clear();
+        self.bump_generation();
     }
 }
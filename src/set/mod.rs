@@ -1,5 +1,15 @@
+pub mod algebra;
+pub mod array;
+mod by_id;
+pub mod cursor;
+pub mod handle;
+pub mod iter;
+mod join;
 pub mod list;
 mod node;
 mod pool;
 pub mod sort;
+pub mod summary;
 pub mod tree;
+mod update;
+pub mod value_mut;
@@ -1,3 +1,5 @@
+use crate::set::summary::Summary;
+
 #[derive(PartialEq, Clone, Copy)]
 pub(super) enum Color {
     Red,
@@ -5,15 +7,19 @@ pub(super) enum Color {
 }
 
 #[derive(Clone)]
-pub(super) struct Node<V> {
+pub(super) struct Node<V, S = ()> {
     pub(super) parent: u32,
     pub(super) left: u32,
     pub(super) right: u32,
     pub(super) color: Color,
+    // odd while the node is in use
+    pub(super) generation: u32,
     pub(super) value: V,
+    // aggregate of the subtree
+    pub(super) summary: S,
 }
 
-impl<V: Clone + Default> Default for Node<V> {
+impl<V: Clone + Default, S: Summary<V>> Default for Node<V, S> {
     #[inline]
     fn default() -> Self {
         Self {
@@ -21,7 +27,9 @@ impl<V: Clone + Default> Default for Node<V> {
             left: 0,
             right: 0,
             color: Color::Red,
+            generation: 0,
             value: V::default(),
+            summary: S::identity(),
         }
     }
 }
@@ -1,12 +1,13 @@
 use crate::set::node::Node;
+use crate::set::summary::Summary;
 use alloc::vec::Vec;
 
-pub(super) struct Pool<V> {
-    pub(super) buffer: Vec<Node<V>>,
+pub(super) struct Pool<V, S = ()> {
+    pub(super) buffer: Vec<Node<V, S>>,
     pub(super) unused: Vec<u32>,
 }
 
-impl<V: Clone + Default> Pool<V> {
+impl<V: Clone + Default, S: Summary<V>> Pool<V, S> {
     #[inline]
     pub(super) fn new(capacity: usize) -> Self {
         let capacity = capacity.max(8);
@@ -34,13 +35,18 @@ impl<V: Clone + Default> Pool<V> {
         if self.unused.is_empty() {
             self.reserve(self.unused.capacity());
         }
-        self.unused.pop().unwrap()
+        let index = self.unused.pop().unwrap();
+        let node = unsafe { self.buffer.get_unchecked_mut(index as usize) };
+        node.generation = node.generation.wrapping_add(1);
+        index
     }
 }
 
-impl<V> Pool<V> {
+impl<V, S> Pool<V, S> {
     #[inline(always)]
     pub(super) fn put_back(&mut self, index: u32) {
+        let node = unsafe { self.buffer.get_unchecked_mut(index as usize) };
+        node.generation = node.generation.wrapping_add(1);
         self.unused.push(index)
     }
 }
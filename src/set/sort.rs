@@ -1,26 +1,157 @@
+use crate::EMPTY_REF;
+use crate::set::cursor::{Cursor, CursorMut};
+use crate::set::handle::Handle;
 use core::cmp::Ordering;
+use core::ops::{DerefMut, RangeBounds};
 
 pub trait KeyValue<K> {
     fn key(&self) -> &K;
 }
 
 pub trait SetCollection<K, V> {
+    /// Mutable access to a stored value returned by `get_mut`.
+    type ValueMut<'a>: DerefMut<Target = V>
+    where
+        Self: 'a;
+
     fn is_empty(&self) -> bool;
-    fn insert(&mut self, val: V);
+    fn insert(&mut self, val: V) -> u32;
     fn delete(&mut self, key: &K);
     fn delete_by_index(&mut self, index: u32);
+    fn take(&mut self, key: &K) -> Option<V>;
+    fn pop_first(&mut self) -> Option<V>;
+    fn pop_last(&mut self) -> Option<V>;
+    /// Keeps only the values for which `pred` returns true.
+    fn retain<P>(&mut self, pred: P)
+    where
+        P: FnMut(&V) -> bool;
+    fn remove_range<R>(&mut self, range: R)
+    where
+        R: RangeBounds<K>;
     fn get_value(&self, key: &K) -> Option<&V>;
-    fn index_after(&self, index: u32) -> u32;
-    fn index_before(&self, index: u32) -> u32;
+    /// Handle of the value after the one at `index`, or `None` if there is no such value or
+    /// `index` does not point to a stored value.
+    fn index_after(&self, index: u32) -> Option<Handle>;
+    /// Same as `index_after`, but for the value before.
+    fn index_before(&self, index: u32) -> Option<Handle>;
+    fn handle(&self, index: u32) -> Option<Handle>;
+    fn get(&self, handle: Handle) -> Option<&V>;
+    /// The key of the value must not be changed through the returned value.
+    fn get_mut(&mut self, handle: Handle) -> Option<Self::ValueMut<'_>>;
+    fn next(&self, handle: Handle) -> Option<Handle>;
+    fn prev(&self, handle: Handle) -> Option<Handle>;
+    fn first_handle(&self) -> Option<Handle>;
+    fn last_handle(&self) -> Option<Handle>;
+    fn remove(&mut self, handle: Handle) -> Option<V>;
+    /// Inserts `val` right before the value of `handle` without a search from the root.
+    /// Returns `None` and leaves the collection unchanged if the handle is stale or `val` does
+    /// not fit between the value and its predecessor. Like `insert`, it accepts equal keys.
+    fn insert_before(&mut self, handle: Handle, val: V) -> Option<u32>;
+    /// Same as `insert_before`, but on the successor side.
+    fn insert_after(&mut self, handle: Handle, val: V) -> Option<u32>;
+    /// Index of the last value with a key less than or equal to `key`.
     fn first_index_less(&self, key: &K) -> u32;
     fn first_index_less_by<F>(&self, f: F) -> u32
     where
         F: Fn(&K) -> Ordering;
+    fn first_index_less_strict(&self, key: &K) -> u32;
+    fn first_index_less_strict_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering;
+    fn first_index_greater(&self, key: &K) -> u32;
+    fn first_index_greater_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering;
+    fn first_index_greater_or_equal(&self, key: &K) -> u32;
+    fn first_index_greater_or_equal_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering;
+    /// Index of the last value with a key less than `key` that matches `pred`.
+    #[inline]
+    fn first_less_where<P>(&self, key: &K, pred: P) -> u32
+    where
+        P: Fn(&V) -> bool,
+    {
+        let mut index = self.first_index_less_strict(key);
+        while index != EMPTY_REF && !pred(unsafe { self.value_by_index(index) }) {
+            index = self
+                .index_before(index)
+                .map_or(EMPTY_REF, |handle| handle.index());
+        }
+        index
+    }
 
+    /// Index of the first value with a key greater than `key` that matches `pred`.
+    #[inline]
+    fn first_greater_where<P>(&self, key: &K, pred: P) -> u32
+    where
+        P: Fn(&V) -> bool,
+    {
+        let mut index = self.first_index_greater(key);
+        while index != EMPTY_REF && !pred(unsafe { self.value_by_index(index) }) {
+            index = self.index_after(index).map_or(EMPTY_REF, |handle| handle.index());
+        }
+        index
+    }
+
+    /// # Safety
+    ///
+    /// `index` must point to a value that is currently stored in the collection.
     unsafe fn value_by_index(&self, index: u32) -> &V;
+
+    /// # Safety
+    ///
+    /// `index` must point to a value that is currently stored in the collection. The key of the
+    /// value must not be changed, and neither must its id if the collection keeps an id index.
+    /// A tree with a summary does not see the change until `refresh_summary` is called with
+    /// the handle of the value.
     unsafe fn value_by_index_mut(&mut self, index: u32) -> &mut V;
 
     fn clear(&mut self);
+
+    /// Index of the first value with a key not less than `key`.
+    #[inline]
+    fn lower_bound(&self, key: &K) -> u32 {
+        self.first_index_greater_or_equal(key)
+    }
+
+    #[inline]
+    fn lower_bound_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering,
+    {
+        self.first_index_greater_or_equal_by(f)
+    }
+
+    /// Index of the first value with a key greater than `key`.
+    #[inline]
+    fn upper_bound(&self, key: &K) -> u32 {
+        self.first_index_greater(key)
+    }
+
+    #[inline]
+    fn upper_bound_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering,
+    {
+        self.first_index_greater_by(f)
+    }
+
+    #[inline]
+    fn cursor(&self, handle: Handle) -> Cursor<'_, K, V, Self>
+    where
+        Self: Sized,
+    {
+        Cursor::new(self, handle)
+    }
+
+    #[inline]
+    fn cursor_mut(&mut self, handle: Handle) -> CursorMut<'_, K, V, Self>
+    where
+        Self: Sized,
+    {
+        CursorMut::new(self, handle)
+    }
 }
 
 impl KeyValue<i8> for i8 {
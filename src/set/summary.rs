@@ -0,0 +1,123 @@
+use crate::EMPTY_REF;
+use crate::set::handle::Handle;
+use crate::set::sort::{KeyValue, SetCollection};
+use crate::set::tree::SetTree;
+use core::ops::{Bound, RangeBounds};
+
+/// An associative aggregate of values, kept for every subtree of a `SetTree`.
+///
+/// `combine` must be associative and `identity` must be its neutral element. It does not have
+/// to be commutative, values are always combined in key order.
+pub trait Summary<V>: Clone {
+    fn identity() -> Self;
+    fn from_value(val: &V) -> Self;
+    fn combine(&self, other: &Self) -> Self;
+}
+
+impl<V> Summary<V> for () {
+    #[inline(always)]
+    fn identity() -> Self {}
+
+    #[inline(always)]
+    fn from_value(_val: &V) -> Self {}
+
+    #[inline(always)]
+    fn combine(&self, _other: &Self) -> Self {}
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> SetTree<K, V, S> {
+    /// Summary of all values.
+    #[inline]
+    pub fn summary(&self) -> S {
+        if self.root == EMPTY_REF {
+            S::identity()
+        } else {
+            self.node(self.root).summary.clone()
+        }
+    }
+
+    /// Summary of the values with a key inside `range` in O(log n).
+    pub fn fold_range<R: RangeBounds<K>>(&self, range: R) -> S {
+        let start = range.start_bound();
+        let end = range.end_bound();
+        let is_after_start = |k: &K| match start {
+            Bound::Included(s) => k >= s,
+            Bound::Excluded(s) => k > s,
+            Bound::Unbounded => true,
+        };
+        let is_before_end = |k: &K| match end {
+            Bound::Included(e) => k <= e,
+            Bound::Excluded(e) => k < e,
+            Bound::Unbounded => true,
+        };
+
+        // the first node inside the range splits the search into two paths
+        let mut index = self.root;
+        while index != EMPTY_REF {
+            let node = self.node(index);
+            let key = node.value.key();
+            if !is_after_start(key) {
+                index = node.right;
+            } else if !is_before_end(key) {
+                index = node.left;
+            } else {
+                break;
+            }
+        }
+        if index == EMPTY_REF {
+            return S::identity();
+        }
+
+        let node = self.node(index);
+
+        // suffix of the left subtree, collected from right to left
+        let mut left = S::identity();
+        let mut i = node.left;
+        while i != EMPTY_REF {
+            let n = self.node(i);
+            if is_after_start(n.value.key()) {
+                let piece = S::from_value(&n.value).combine(&self.subtree_summary(n.right));
+                left = piece.combine(&left);
+                i = n.left;
+            } else {
+                i = n.right;
+            }
+        }
+
+        // prefix of the right subtree, collected from left to right
+        let mut right = S::identity();
+        let mut i = node.right;
+        while i != EMPTY_REF {
+            let n = self.node(i);
+            if is_before_end(n.value.key()) {
+                let piece = self.subtree_summary(n.left).combine(&S::from_value(&n.value));
+                right = right.combine(&piece);
+                i = n.right;
+            } else {
+                i = n.left;
+            }
+        }
+
+        left.combine(&S::from_value(&node.value)).combine(&right)
+    }
+
+    /// Recomputes the summaries above the value of `handle` after it was changed through a
+    /// mutable reference. Returns false if the handle is stale.
+    #[inline]
+    pub fn refresh_summary(&mut self, handle: Handle) -> bool {
+        if self.get(handle).is_none() {
+            return false;
+        }
+        self.update_summary_to_root(handle.index);
+        true
+    }
+
+    #[inline]
+    fn subtree_summary(&self, index: u32) -> S {
+        if index == EMPTY_REF {
+            S::identity()
+        } else {
+            self.node(index).summary.clone()
+        }
+    }
+}
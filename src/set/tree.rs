@@ -1,13 +1,19 @@
 use crate::EMPTY_REF;
+use crate::id_index::IdIndex;
+use crate::set::handle::Handle;
 use crate::set::node::{Color, Node};
 use crate::set::pool::Pool;
 use crate::set::sort::{KeyValue, SetCollection};
+use crate::set::summary::Summary;
+use crate::set::value_mut::ValueMut;
 use core::cmp::Ordering;
 use core::marker::PhantomData;
+use core::ops::{Bound, RangeBounds};
 
-pub struct SetTree<K, V> {
-    pub(super) store: Pool<V>,
+pub struct SetTree<K, V, S = ()> {
+    pub(super) store: Pool<V, S>,
     pub(super) root: u32,
+    pub(super) ids: Option<IdIndex<V>>,
     phantom_data: PhantomData<K>,
 }
 
@@ -16,25 +22,39 @@ const NIL_INDEX: u32 = 0;
 impl<K, V: Clone + Default> SetTree<K, V> {
     #[inline]
     pub fn new(capacity: usize) -> Self {
+        Self::with_summary(capacity)
+    }
+}
+
+impl<K, V: Clone + Default, S: Summary<V>> SetTree<K, V, S> {
+    /// Creates a tree that keeps the summary `S` of every subtree.
+    #[inline]
+    pub fn with_summary(capacity: usize) -> Self {
         let mut store = Pool::new(capacity);
         let nil_index = store.get_free_index();
         assert_eq!(nil_index, NIL_INDEX);
         Self {
             store,
             root: EMPTY_REF,
+            ids: None,
             phantom_data: Default::default(),
         }
     }
 }
-impl<K: Ord, V: KeyValue<K> + Clone + Default> SetCollection<K, V> for SetTree<K, V> {
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> SetCollection<K, V> for SetTree<K, V, S> {
+    type ValueMut<'a>
+        = ValueMut<'a, K, V, S>
+    where
+        Self: 'a;
+
     #[inline]
     fn is_empty(&self) -> bool {
         self.root == EMPTY_REF
     }
 
     #[inline]
-    fn insert(&mut self, val: V) {
-        self.insert_value(val);
+    fn insert(&mut self, val: V) -> u32 {
+        self.insert_value(val)
     }
 
     #[inline]
@@ -50,44 +70,183 @@ impl<K: Ord, V: KeyValue<K> + Clone + Default> SetCollection<K, V> for SetTree<K
         self.delete_index(index);
     }
 
+    #[inline]
+    fn take(&mut self, key: &K) -> Option<V> {
+        let index = self.find_index(key);
+        if index == EMPTY_REF {
+            return None;
+        }
+        Some(self.take_index(index))
+    }
+
+    #[inline]
+    fn pop_first(&mut self) -> Option<V> {
+        if self.root == EMPTY_REF {
+            return None;
+        }
+        let index = self.find_left_minimum(self.root);
+        Some(self.take_index(index))
+    }
+
+    #[inline]
+    fn pop_last(&mut self) -> Option<V> {
+        if self.root == EMPTY_REF {
+            return None;
+        }
+        let index = self.find_right_minimum(self.root);
+        Some(self.take_index(index))
+    }
+
+    fn retain<P>(&mut self, mut pred: P)
+    where
+        P: FnMut(&V) -> bool,
+    {
+        let mut index = self.first_handle().map_or(EMPTY_REF, |h| h.index());
+        while index != EMPTY_REF {
+            // indices stay valid on delete, so the next one can be taken first
+            let next = self.next_index(index);
+            if !pred(&self.node(index).value) {
+                self.delete_index(index);
+            }
+            index = next;
+        }
+    }
+
+    fn remove_range<R>(&mut self, range: R)
+    where
+        R: RangeBounds<K>,
+    {
+        if self.root == EMPTY_REF {
+            return;
+        }
+        let all = (self.root, self.black_height(self.root));
+        let (left, rest) = match range.start_bound() {
+            Bound::Included(key) => self.split_by(all, &|k: &K| k < key),
+            Bound::Excluded(key) => self.split_by(all, &|k: &K| k <= key),
+            Bound::Unbounded => ((EMPTY_REF, 0), all),
+        };
+        let (mid, right) = match range.end_bound() {
+            Bound::Included(key) => self.split_by(rest, &|k: &K| k <= key),
+            Bound::Excluded(key) => self.split_by(rest, &|k: &K| k < key),
+            Bound::Unbounded => (rest, (EMPTY_REF, 0)),
+        };
+        self.put_back_subtree(mid.0);
+        self.join_two(left, right);
+    }
+
     #[inline]
     fn get_value(&self, key: &K) -> Option<&V> {
         self.search_value(key)
     }
 
     #[inline]
-    fn index_after(&self, mut index: u32) -> u32 {
-        let node = self.node(index);
-        if node.right != EMPTY_REF {
-            self.find_left_minimum(node.right)
+    fn index_after(&self, index: u32) -> Option<Handle> {
+        self.handle(index)?;
+        self.to_handle(self.next_index(index))
+    }
+
+    #[inline]
+    fn index_before(&self, index: u32) -> Option<Handle> {
+        self.handle(index)?;
+        self.to_handle(self.prev_index(index))
+    }
+
+    #[inline]
+    fn handle(&self, index: u32) -> Option<Handle> {
+        if index == NIL_INDEX || index as usize >= self.store.buffer.len() {
+            return None;
+        }
+        let generation = self.node(index).generation;
+        if generation & 1 == 1 {
+            Some(Handle::new(index, generation))
         } else {
-            // find first parent where we not right
-            let mut parent_index = node.parent;
-            let mut parent = self.node(parent_index);
-            while parent.right == index {
-                index = parent_index;
-                parent_index = parent.parent;
-                parent = self.node(parent_index);
-            }
-            parent_index
+            None
         }
     }
 
     #[inline]
-    fn index_before(&self, mut index: u32) -> u32 {
-        let node = self.node(index);
-        if node.left != EMPTY_REF {
-            self.find_right_minimum(node.left)
+    fn get(&self, handle: Handle) -> Option<&V> {
+        self.store
+            .buffer
+            .get(handle.index as usize)
+            .filter(|node| node.generation == handle.generation)
+            .map(|node| &node.value)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, handle: Handle) -> Option<ValueMut<'_, K, V, S>> {
+        self.get(handle)?;
+        Some(ValueMut::new(self, handle.index))
+    }
+
+    #[inline]
+    fn next(&self, handle: Handle) -> Option<Handle> {
+        self.get(handle)?;
+        self.index_after(handle.index)
+    }
+
+    #[inline]
+    fn prev(&self, handle: Handle) -> Option<Handle> {
+        self.get(handle)?;
+        self.index_before(handle.index)
+    }
+
+    #[inline]
+    fn first_handle(&self) -> Option<Handle> {
+        if self.root == EMPTY_REF {
+            return None;
+        }
+        self.to_handle(self.find_left_minimum(self.root))
+    }
+
+    #[inline]
+    fn last_handle(&self) -> Option<Handle> {
+        if self.root == EMPTY_REF {
+            return None;
+        }
+        self.to_handle(self.find_right_minimum(self.root))
+    }
+
+    #[inline]
+    fn remove(&mut self, handle: Handle) -> Option<V> {
+        let val = self.get(handle)?.clone();
+        self.delete_index(handle.index);
+        Some(val)
+    }
+
+    #[inline]
+    fn insert_before(&mut self, handle: Handle, val: V) -> Option<u32> {
+        let index = handle.index;
+        if val.key() > self.get(handle)?.key() {
+            return None;
+        }
+        let prev = self.prev_index(index);
+        if prev != EMPTY_REF && self.node(prev).value.key() > val.key() {
+            return None;
+        }
+        // prev is the right minimum of the left subtree, so it has no right child
+        if self.node(index).left == EMPTY_REF {
+            Some(self.insert_as_left(val, index))
         } else {
-            // find first parent where we not left
-            let mut parent_index = node.parent;
-            let mut parent = self.node(parent_index);
-            while parent.left == index {
-                index = parent_index;
-                parent_index = parent.parent;
-                parent = self.node(parent_index);
-            }
-            parent_index
+            Some(self.insert_as_right(val, prev))
+        }
+    }
+
+    #[inline]
+    fn insert_after(&mut self, handle: Handle, val: V) -> Option<u32> {
+        let index = handle.index;
+        if val.key() < self.get(handle)?.key() {
+            return None;
+        }
+        let next = self.next_index(index);
+        if next != EMPTY_REF && self.node(next).value.key() < val.key() {
+            return None;
+        }
+        // next is the left minimum of the right subtree, so it has no left child
+        if self.node(index).right == EMPTY_REF {
+            Some(self.insert_as_right(val, index))
+        } else {
+            Some(self.insert_as_left(val, next))
         }
     }
 
@@ -104,6 +263,45 @@ impl<K: Ord, V: KeyValue<K> + Clone + Default> SetCollection<K, V> for SetTree<K
         self.search_first_less_by(f)
     }
 
+    #[inline]
+    fn first_index_less_strict(&self, key: &K) -> u32 {
+        self.search_last_by(|k| k.cmp(key) == Ordering::Less)
+    }
+
+    #[inline]
+    fn first_index_less_strict_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering,
+    {
+        self.search_last_by(|k| f(k) == Ordering::Less)
+    }
+
+    #[inline]
+    fn first_index_greater(&self, key: &K) -> u32 {
+        self.search_first_by(|k| k.cmp(key) == Ordering::Greater)
+    }
+
+    #[inline]
+    fn first_index_greater_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering,
+    {
+        self.search_first_by(|k| f(k) == Ordering::Greater)
+    }
+
+    #[inline]
+    fn first_index_greater_or_equal(&self, key: &K) -> u32 {
+        self.search_first_by(|k| k.cmp(key) != Ordering::Less)
+    }
+
+    #[inline]
+    fn first_index_greater_or_equal_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering,
+    {
+        self.search_first_by(|k| f(k) != Ordering::Less)
+    }
+
     unsafe fn value_by_index(&self, index: u32) -> &V {
         &self.node(index).value
     }
@@ -114,50 +312,89 @@ impl<K: Ord, V: KeyValue<K> + Clone + Default> SetCollection<K, V> for SetTree<K
     }
 
     fn clear(&mut self) {
-        if self.root == EMPTY_REF {
-            return;
+        if let Some(ids) = &mut self.ids {
+            ids.clear();
         }
-        self.store.put_back(self.root);
+        let root = self.root;
         self.root = EMPTY_REF;
-
-        let mut n = 1;
-        while n > 0 {
-            let i0 = self.store.unused.len() - n;
-            n = 0;
-            for i in i0..self.store.unused.len() {
-                let index = self.store.unused[i];
-                let node = self.node(index);
-                let left = node.left;
-                let right = node.right;
-                if left != EMPTY_REF {
-                    self.store.put_back(left);
-                    n += 1;
-                }
-                if right != EMPTY_REF {
-                    self.store.put_back(right);
-                    n += 1;
-                }
-            }
-        }
+        self.put_back_subtree(root);
     }
 }
 
-impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
+impl<K: Ord, V: Clone + Default + KeyValue<K>, S: Summary<V>> SetTree<K, V, S> {
     #[inline(always)]
-    fn is_black(&self, index: u32) -> bool {
+    pub(super) fn is_black(&self, index: u32) -> bool {
         index == EMPTY_REF || self.node(index).color == Color::Black
     }
 
     #[inline(always)]
-    pub(super) fn node(&self, index: u32) -> &Node<V> {
+    pub(super) fn node(&self, index: u32) -> &Node<V, S> {
         unsafe { self.store.buffer.get_unchecked(index as usize) }
     }
 
     #[inline(always)]
-    pub(super) fn node_mut(&mut self, index: u32) -> &mut Node<V> {
+    pub(super) fn node_mut(&mut self, index: u32) -> &mut Node<V, S> {
         unsafe { self.store.buffer.get_unchecked_mut(index as usize) }
     }
 
+    #[inline]
+    pub(super) fn register_id(&mut self, index: u32) {
+        if let Some(ids) = &mut self.ids {
+            let node = unsafe { self.store.buffer.get_unchecked(index as usize) };
+            ids.set(&node.value, index);
+        }
+    }
+
+    // the id the index holds for the value, 0 without an index
+    #[inline]
+    pub(super) fn current_id(&self, index: u32) -> usize {
+        self.ids.as_ref().map_or(0, |ids| ids.id(&self.node(index).value))
+    }
+
+    #[inline]
+    pub(super) fn replace_id(&mut self, old_id: usize, index: u32) {
+        if let Some(ids) = &mut self.ids {
+            let node = unsafe { self.store.buffer.get_unchecked(index as usize) };
+            ids.replace(old_id, &node.value, index);
+        }
+    }
+
+    #[inline]
+    pub(super) fn unregister_id(&mut self, index: u32) {
+        if let Some(ids) = &mut self.ids {
+            let node = unsafe { self.store.buffer.get_unchecked(index as usize) };
+            ids.remove(&node.value);
+        }
+    }
+
+    #[inline]
+    pub(super) fn update_summary(&mut self, index: u32) {
+        // a zero sized summary carries no data
+        if size_of::<S>() == 0 {
+            return;
+        }
+        let node = self.node(index);
+        let mut summary = S::from_value(&node.value);
+        if node.left != EMPTY_REF {
+            summary = self.node(node.left).summary.combine(&summary);
+        }
+        if node.right != EMPTY_REF {
+            summary = summary.combine(&self.node(node.right).summary);
+        }
+        self.node_mut(index).summary = summary;
+    }
+
+    #[inline]
+    pub(super) fn update_summary_to_root(&mut self, mut index: u32) {
+        if size_of::<S>() == 0 {
+            return;
+        }
+        while index != EMPTY_REF {
+            self.update_summary(index);
+            index = self.node(index).parent;
+        }
+    }
+
     #[inline]
     fn create_nil_node(&mut self, parent: u32) {
         let node = self.node_mut(NIL_INDEX);
@@ -165,18 +402,23 @@ impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
         node.left = EMPTY_REF;
         node.right = EMPTY_REF;
         node.color = Color::Red;
+        node.summary = S::identity();
     }
 
     #[inline]
-    fn insert_root(&mut self, value: V) {
+    fn insert_root(&mut self, value: V) -> u32 {
         let new_index = self.store.get_free_index();
         let new_node = self.node_mut(new_index);
         new_node.parent = EMPTY_REF;
         new_node.left = EMPTY_REF;
         new_node.right = EMPTY_REF;
         new_node.color = Color::Black;
+        new_node.summary = S::from_value(&value);
         new_node.value = value;
         self.root = new_index;
+        self.register_id(new_index);
+
+        new_index
     }
 
     #[inline]
@@ -197,39 +439,53 @@ impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
 
     #[inline]
     fn search_first_less(&self, key: &K) -> u32 {
+        self.search_last_by(|k| k <= key)
+    }
+
+    #[inline]
+    fn search_first_less_by<F>(&self, f: F) -> u32
+    where
+        F: Fn(&K) -> Ordering,
+    {
+        self.search_last_by(|k| f(k) != Ordering::Greater)
+    }
+
+    // last value where `is_before` holds, `is_before` must be true for a prefix of the keys
+    #[inline]
+    fn search_last_by<F>(&self, is_before: F) -> u32
+    where
+        F: Fn(&K) -> bool,
+    {
         let mut index = self.root;
         let mut result = EMPTY_REF;
         while index != EMPTY_REF {
             let node = self.node(index);
-            match node.value.key().cmp(key) {
-                Ordering::Equal => return index,
-                Ordering::Less => {
-                    result = index;
-                    index = node.right;
-                }
-                Ordering::Greater => index = node.left,
+            if is_before(node.value.key()) {
+                result = index;
+                index = node.right;
+            } else {
+                index = node.left;
             }
         }
 
         result
     }
 
+    // first value where `is_after` holds, `is_after` must be true for a suffix of the keys
     #[inline]
-    fn search_first_less_by<F>(&self, f: F) -> u32
+    fn search_first_by<F>(&self, is_after: F) -> u32
     where
-        F: Fn(&K) -> Ordering,
+        F: Fn(&K) -> bool,
     {
         let mut index = self.root;
         let mut result = EMPTY_REF;
         while index != EMPTY_REF {
             let node = self.node(index);
-            match f(node.value.key()) {
-                Ordering::Equal => return index,
-                Ordering::Less => {
-                    result = index;
-                    index = node.right;
-                }
-                Ordering::Greater => index = node.left,
+            if is_after(node.value.key()) {
+                result = index;
+                index = node.left;
+            } else {
+                index = node.right;
             }
         }
 
@@ -253,11 +509,10 @@ impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
     }
 
     #[inline]
-    fn insert_value(&mut self, value: V) {
+    pub(super) fn insert_value(&mut self, value: V) -> u32 {
         let mut index = self.root;
         if index == EMPTY_REF {
-            self.insert_root(value);
-            return;
+            return self.insert_root(value);
         }
 
         let key = value.key();
@@ -268,19 +523,60 @@ impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
             if key < node.value.key() {
                 index = node.left;
                 if index == EMPTY_REF {
-                    self.insert_as_left(value, p_index);
-                    return;
+                    return self.insert_as_left(value, p_index);
                 }
             } else {
                 index = node.right;
                 if index == EMPTY_REF {
-                    self.insert_as_right(value, p_index);
-                    return;
+                    return self.insert_as_right(value, p_index);
                 }
             }
         }
     }
 
+    // links a node that is not part of the tree, its slot and index are kept
+    pub(super) fn link_index(&mut self, index: u32) {
+        let summary = S::from_value(&self.node(index).value);
+        let node = self.node_mut(index);
+        node.left = EMPTY_REF;
+        node.right = EMPTY_REF;
+        node.summary = summary;
+
+        if self.root == EMPTY_REF {
+            let node = self.node_mut(index);
+            node.parent = EMPTY_REF;
+            node.color = Color::Black;
+            self.root = index;
+            return;
+        }
+
+        let mut p_index = self.root;
+        let is_left = loop {
+            let key = self.node(index).value.key();
+            let parent = self.node(p_index);
+            let is_left = key < parent.value.key();
+            let child = if is_left { parent.left } else { parent.right };
+            if child == EMPTY_REF {
+                break is_left;
+            }
+            p_index = child;
+        };
+
+        let node = self.node_mut(index);
+        node.parent = p_index;
+        node.color = Color::Red;
+        if is_left {
+            self.node_mut(p_index).left = index;
+        } else {
+            self.node_mut(p_index).right = index;
+        }
+        self.update_summary_to_root(p_index);
+
+        if self.node(p_index).color == Color::Red {
+            self.fix_red_black_properties_after_insert(index, p_index);
+        }
+    }
+
     #[inline]
     fn insert_new(&mut self, value: V, p_index: u32) -> u32 {
         let new_index = self.store.get_free_index();
@@ -289,36 +585,42 @@ impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
         new_node.left = EMPTY_REF;
         new_node.right = EMPTY_REF;
         new_node.color = Color::Red;
+        new_node.summary = S::from_value(&value);
         new_node.value = value;
+        self.register_id(new_index);
 
         new_index
     }
 
     #[inline]
-    fn insert_as_left(&mut self, value: V, p_index: u32) {
+    fn insert_as_left(&mut self, value: V, p_index: u32) -> u32 {
         let new_index = self.insert_new(value, p_index);
 
-        let parent = self.node_mut(p_index);
-        parent.left = new_index;
+        self.node_mut(p_index).left = new_index;
+        self.update_summary_to_root(p_index);
 
-        if parent.color == Color::Red {
+        if self.node(p_index).color == Color::Red {
             self.fix_red_black_properties_after_insert(new_index, p_index);
         }
+
+        new_index
     }
 
     #[inline]
-    fn insert_as_right(&mut self, value: V, p_index: u32) {
+    fn insert_as_right(&mut self, value: V, p_index: u32) -> u32 {
         let new_index = self.insert_new(value, p_index);
 
-        let parent = self.node_mut(p_index);
-        parent.right = new_index;
+        self.node_mut(p_index).right = new_index;
+        self.update_summary_to_root(p_index);
 
-        if parent.color == Color::Red {
+        if self.node(p_index).color == Color::Red {
             self.fix_red_black_properties_after_insert(new_index, p_index);
         }
+
+        new_index
     }
 
-    fn fix_red_black_properties_after_insert(&mut self, n_index: u32, p_origin: u32) {
+    pub(super) fn fix_red_black_properties_after_insert(&mut self, n_index: u32, p_origin: u32) {
         // parent is red!
         let mut p_index = p_origin;
         // Case 2:
@@ -402,6 +704,8 @@ impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
         node.parent = lt_index;
 
         self.replace_parents_child(p, index, lt_index);
+        self.update_summary(index);
+        self.update_summary(lt_index);
     }
 
     fn rotate_left(&mut self, index: u32) {
@@ -421,6 +725,8 @@ impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
         node.parent = rt_index;
 
         self.replace_parents_child(p, index, rt_index);
+        self.update_summary(index);
+        self.update_summary(rt_index);
     }
 
     #[inline]
@@ -445,7 +751,56 @@ impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
     }
 
     #[inline]
-    fn find_left_minimum(&self, mut i: u32) -> u32 {
+    pub(super) fn next_index(&self, mut index: u32) -> u32 {
+        let node = self.node(index);
+        if node.right != EMPTY_REF {
+            self.find_left_minimum(node.right)
+        } else {
+            // find first parent where we not right
+            let mut parent_index = node.parent;
+            while parent_index != EMPTY_REF {
+                let parent = self.node(parent_index);
+                if parent.right != index {
+                    break;
+                }
+                index = parent_index;
+                parent_index = parent.parent;
+            }
+            parent_index
+        }
+    }
+
+    #[inline]
+    pub(super) fn prev_index(&self, mut index: u32) -> u32 {
+        let node = self.node(index);
+        if node.left != EMPTY_REF {
+            self.find_right_minimum(node.left)
+        } else {
+            // find first parent where we not left
+            let mut parent_index = node.parent;
+            while parent_index != EMPTY_REF {
+                let parent = self.node(parent_index);
+                if parent.left != index {
+                    break;
+                }
+                index = parent_index;
+                parent_index = parent.parent;
+            }
+            parent_index
+        }
+    }
+
+    #[inline]
+    pub(super) fn to_handle(&self, index: u32) -> Option<Handle> {
+        if index == EMPTY_REF {
+            None
+        } else {
+            Some(Handle::new(index, self.node(index).generation))
+        }
+    }
+
+    #[inline]
+    pub(super) fn find_left_minimum(&self, mut i: u32) -> u32 {
         while self.node(i).left != EMPTY_REF {
             i = self.node(i).left;
         }
@@ -453,45 +808,53 @@ impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
     }
 
     #[inline]
-    fn find_right_minimum(&self, mut i: u32) -> u32 {
+    pub(super) fn find_right_minimum(&self, mut i: u32) -> u32 {
         while self.node(i).right != EMPTY_REF {
             i = self.node(i).right;
         }
         i
     }
 
+    #[inline]
+    fn take_index(&mut self, index: u32) -> V {
+        self.delete_index(index);
+        // the freed slot still holds the value
+        core::mem::take(&mut self.node_mut(index).value)
+    }
+
     pub(super) fn delete_index(&mut self, index: u32) {
-        // Node has zero or one child
-        let mut delete_index = index;
+        if let Some(ids) = &mut self.ids {
+            let node = unsafe { self.store.buffer.get_unchecked(index as usize) };
+            ids.remove(&node.value);
+        }
+        self.unlink_index(index);
+        self.store.put_back(index);
+    }
 
+    // removes the node from the tree but keeps its slot
+    pub(super) fn unlink_index(&mut self, index: u32) {
+        // if two children relink node with it left minimum, so indices of other nodes stay valid
         let node = self.node(index);
-        let mut nd_left = node.left;
-        let mut nd_right = node.right;
-        let mut nd_parent = node.parent;
-        let mut nd_color = node.color;
-
-        // if two children replace node with it left minimum
-        if nd_left != EMPTY_REF && nd_right != EMPTY_REF {
-            let successor_index = self.find_left_minimum(nd_right);
-            let successor = self.node(successor_index);
-            let value = successor.value.clone();
-            nd_parent = successor.parent;
-            nd_left = successor.left;
-            nd_right = successor.right;
-            nd_color = successor.color;
-
-            self.node_mut(index).value = value;
-
-            delete_index = successor_index;
+        if node.left != EMPTY_REF && node.right != EMPTY_REF {
+            let successor_index = self.find_left_minimum(node.right);
+            self.swap_with_successor(index, successor_index);
         }
 
+        let node = self.node(index);
+        let nd_left = node.left;
+        let nd_right = node.right;
+        let nd_parent = node.parent;
+        let nd_color = node.color;
+
         // only one child can be!
 
         if nd_left != EMPTY_REF {
-            self.replace_parents_child(nd_parent, delete_index, nd_left);
+            self.replace_parents_child(nd_parent, index, nd_left);
+            self.update_summary_to_root(nd_parent);
             self.fix_red_black_properties_after_delete(nd_left);
         } else if nd_right != EMPTY_REF {
-            self.replace_parents_child(nd_parent, delete_index, nd_right);
+            self.replace_parents_child(nd_parent, index, nd_right);
+            self.update_summary_to_root(nd_parent);
             self.fix_red_black_properties_after_delete(nd_right);
         } else if nd_parent == EMPTY_REF {
             self.root = EMPTY_REF;
@@ -501,15 +864,53 @@ impl<K: Ord, V: Clone + Default + KeyValue<K>> SetTree<K, V> {
             // * node is black --> replace it by a temporary NIL node (needed to fix the R-B rules)
             if nd_color == Color::Black {
                 self.create_nil_node(nd_parent);
-                self.set_nil_parents_child(nd_parent, delete_index);
+                self.set_nil_parents_child(nd_parent, index);
+                self.update_summary_to_root(nd_parent);
                 self.fix_red_black_properties_after_delete(NIL_INDEX);
                 self.fix_parents_nil_child();
             } else {
-                self.remove_parents_child(nd_parent, delete_index);
+                self.remove_parents_child(nd_parent, index);
+                self.update_summary_to_root(nd_parent);
             }
         }
+    }
+
+    // successor is the left minimum of the right subtree of index
+    fn swap_with_successor(&mut self, index: u32, successor: u32) {
+        let node = self.node(index);
+        let nd_parent = node.parent;
+        let nd_left = node.left;
+        let nd_right = node.right;
+        let nd_color = node.color;
+
+        let successor_node = self.node(successor);
+        let sc_parent = successor_node.parent;
+        let sc_right = successor_node.right;
+        let sc_color = successor_node.color;
+
+        self.replace_parents_child(nd_parent, index, successor);
+
+        self.node_mut(successor).left = nd_left;
+        self.node_mut(nd_left).parent = successor;
+
+        if sc_parent == index {
+            self.node_mut(successor).right = index;
+            self.node_mut(index).parent = successor;
+        } else {
+            self.node_mut(successor).right = nd_right;
+            self.node_mut(nd_right).parent = successor;
+            self.node_mut(sc_parent).left = index;
+            self.node_mut(index).parent = sc_parent;
+        }
+
+        self.node_mut(index).left = EMPTY_REF;
+        self.node_mut(index).right = sc_right;
+        if sc_right != EMPTY_REF {
+            self.node_mut(sc_right).parent = index;
+        }
 
-        self.store.put_back(delete_index);
+        self.node_mut(successor).color = nd_color;
+        self.node_mut(index).color = sc_color;
     }
 
     fn fix_red_black_properties_after_delete(&mut self, n_index: u32) {
@@ -0,0 +1,36 @@
+use crate::EMPTY_REF;
+use crate::set::handle::Handle;
+use crate::set::sort::{KeyValue, SetCollection};
+use crate::set::summary::Summary;
+use crate::set::tree::SetTree;
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> SetTree<K, V, S> {
+    /// Changes the value at `handle` with `f` and moves its node only if the key is no longer
+    /// between its neighbors. The node keeps its index, so handles to it stay valid.
+    ///
+    /// Returns `false` and does not call `f` if the handle is stale.
+    pub fn update_key<F: FnOnce(&mut V)>(&mut self, handle: Handle, f: F) -> bool {
+        if self.get(handle).is_none() {
+            return false;
+        }
+        let index = handle.index;
+        let prev = self.prev_index(index);
+        let next = self.next_index(index);
+
+        self.unregister_id(index);
+        f(&mut self.node_mut(index).value);
+        self.register_id(index);
+
+        let key = self.node(index).value.key();
+        let is_in_order = (prev == EMPTY_REF || self.node(prev).value.key() <= key)
+            && (next == EMPTY_REF || key <= self.node(next).value.key());
+
+        if is_in_order {
+            self.update_summary_to_root(index);
+        } else {
+            self.unlink_index(index);
+            self.link_index(index);
+        }
+        true
+    }
+}
@@ -0,0 +1,100 @@
+use crate::id_index::IdIndex;
+use crate::set::sort::KeyValue;
+use crate::set::summary::Summary;
+use crate::set::tree::SetTree;
+use core::ops::{Deref, DerefMut};
+
+/// Mutable access to a value of a `SetTree`.
+///
+/// The key of the value must not be changed, use `update_key` for that. Any other field,
+/// including the id, can be changed: the id index and the summaries above the value are
+/// updated when the guard is dropped.
+pub struct ValueMut<'a, K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> {
+    tree: &'a mut SetTree<K, V, S>,
+    index: u32,
+    // the id the index holds for the value
+    id: usize,
+}
+
+impl<'a, K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> ValueMut<'a, K, V, S> {
+    #[inline]
+    pub(super) fn new(tree: &'a mut SetTree<K, V, S>, index: u32) -> Self {
+        let id = tree.current_id(index);
+        Self { tree, index, id }
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> Deref for ValueMut<'_, K, V, S> {
+    type Target = V;
+
+    #[inline]
+    fn deref(&self) -> &V {
+        &self.tree.node(self.index).value
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> DerefMut for ValueMut<'_, K, V, S> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut V {
+        &mut self.tree.node_mut(self.index).value
+    }
+}
+
+impl<K: Ord, V: KeyValue<K> + Clone + Default, S: Summary<V>> Drop for ValueMut<'_, K, V, S> {
+    #[inline]
+    fn drop(&mut self) {
+        self.tree.replace_id(self.id, self.index);
+        self.tree.update_summary_to_root(self.index);
+    }
+}
+
+/// A value yielded by `TreeIteratorMut`.
+///
+/// The key must not be changed. A changed id is written to the id index when the guard is
+/// dropped.
+pub struct IterValueMut<'a, V> {
+    value: &'a mut V,
+    // null if the tree has no id index
+    ids: *mut IdIndex<V>,
+    index: u32,
+    id: usize,
+}
+
+impl<'a, V> IterValueMut<'a, V> {
+    #[inline]
+    pub(super) fn new(value: &'a mut V, ids: *mut IdIndex<V>, index: u32) -> Self {
+        // the iterator and its guards stay on one thread, so they use the index one at a time
+        let id = unsafe { ids.as_ref() }.map_or(0, |ids| ids.id(value));
+        Self {
+            value,
+            ids,
+            index,
+            id,
+        }
+    }
+}
+
+impl<V> Deref for IterValueMut<'_, V> {
+    type Target = V;
+
+    #[inline]
+    fn deref(&self) -> &V {
+        self.value
+    }
+}
+
+impl<V> DerefMut for IterValueMut<'_, V> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut V {
+        self.value
+    }
+}
+
+impl<V> Drop for IterValueMut<'_, V> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(ids) = unsafe { self.ids.as_mut() } {
+            ids.replace(self.id, self.value, self.index);
+        }
+    }
+}
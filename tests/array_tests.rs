@@ -1,4 +1,5 @@
 #[cfg(test)]
+#[allow(clippy::non_canonical_partial_ord_impl)]
 mod tests {
     use i_tree::ExpiredKey;
     use i_tree::key::array::IntoArray;
@@ -0,0 +1,227 @@
+#[cfg(test)]
+mod tests {
+    use i_tree::ExpiredKey;
+    use i_tree::key::array::IntoArray;
+    use i_tree::key::exp::KeyExpCollection;
+    use i_tree::key::kinetic::KineticTree;
+    use rand::{RngExt, rng};
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+
+    thread_local! {
+        static SWEEP_X: Cell<i64> = const { Cell::new(0) };
+    }
+
+    // y = y0 + k * x
+    #[derive(Debug, Clone, Copy)]
+    struct Line {
+        y0: i64,
+        k: i64,
+        exp: i64,
+    }
+
+    impl Line {
+        fn y(&self, x: i64) -> i64 {
+            self.y0 + self.k * x
+        }
+    }
+
+    impl Ord for Line {
+        fn cmp(&self, other: &Self) -> Ordering {
+            let x = SWEEP_X.get();
+            self.y(x).cmp(&other.y(x)).then(self.k.cmp(&other.k))
+        }
+    }
+
+    impl Eq for Line {}
+
+    impl PartialEq<Self> for Line {
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other) == Ordering::Equal
+        }
+    }
+
+    impl PartialOrd<Self> for Line {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl ExpiredKey<i64> for Line {
+        fn expiration(&self) -> i64 {
+            self.exp
+        }
+    }
+
+    // first integer x where a is above b
+    fn failure(a: &Line, b: &Line) -> Option<i64> {
+        if a.k <= b.k {
+            return None;
+        }
+        Some((b.y0 - a.y0).div_euclid(a.k - b.k) + 1)
+    }
+
+    #[test]
+    fn test_00() {
+        let mut tree = KineticTree::new(8, failure);
+        let a = Line {
+            y0: 0,
+            k: 1,
+            exp: 100,
+        };
+        let b = Line {
+            y0: 10,
+            k: -1,
+            exp: 100,
+        };
+        tree.insert(a, 'a', 0);
+        tree.insert(b, 'b', 0);
+
+        SWEEP_X.set(4);
+        assert_eq!(tree.first_less(4, '-', b), 'a');
+        assert_eq!(tree.first_less(4, '-', a), '-');
+
+        // cross at x = 5
+        SWEEP_X.set(6);
+        assert_eq!(tree.first_less(6, '-', b), '-');
+        assert_eq!(tree.first_less(6, '-', a), 'b');
+        assert_eq!(tree.get_value(6, a), Some('a'));
+        assert_eq!(tree.get_value(6, b), Some('b'));
+
+        assert_eq!(tree.into_ordered_vec(6), vec!['b', 'a']);
+    }
+
+    #[test]
+    fn test_01() {
+        let mut tree = KineticTree::new(8, failure);
+        let a = Line { y0: 0, k: 2, exp: 3 };
+        let b = Line {
+            y0: 1,
+            k: 0,
+            exp: 100,
+        };
+        let c = Line {
+            y0: 2,
+            k: -2,
+            exp: 100,
+        };
+        tree.insert(a, 'a', 0);
+        tree.insert(b, 'b', 0);
+        tree.insert(c, 'c', 0);
+
+        SWEEP_X.set(10);
+        assert_eq!(tree.into_ordered_vec(10), vec!['c', 'b']);
+    }
+
+    #[test]
+    fn test_02() {
+        // equal lines never cross, but this certificate reports them as already failed
+        let degenerate = |a: &Line, b: &Line| {
+            if a.y0 == b.y0 && a.k == b.k {
+                Some(0)
+            } else {
+                failure(a, b)
+            }
+        };
+        let a = Line {
+            y0: 5,
+            k: 1,
+            exp: 100,
+        };
+        let b = Line {
+            y0: 0,
+            k: 0,
+            exp: 100,
+        };
+
+        for n in 2..5 {
+            let mut tree = KineticTree::new(8, degenerate);
+            SWEEP_X.set(2);
+            tree.insert(b, 'b', 2);
+            for _ in 0..n {
+                tree.insert(a, 'a', 2);
+            }
+
+            SWEEP_X.set(10);
+            let mut template = vec!['b'];
+            template.resize(n + 1, 'a');
+            assert_eq!(tree.into_ordered_vec(10), template);
+        }
+    }
+
+    #[test]
+    fn test_random_00() {
+        let mut rng = rng();
+        for _ in 0..200 {
+            let n = rng.random_range(1..40);
+            let mut lines = Vec::with_capacity(n);
+            for i in 0..n {
+                lines.push(Line {
+                    y0: 1000 * i as i64 + rng.random_range(0..100),
+                    k: rng.random_range(-50..50),
+                    exp: rng.random_range(1..60),
+                });
+            }
+
+            let end = rng.random_range(0..50);
+            let mut tree = KineticTree::new(8, failure);
+            SWEEP_X.set(0);
+            for (i, line) in lines.iter().enumerate() {
+                tree.insert(*line, i, 0);
+            }
+
+            SWEEP_X.set(end);
+            let ys: Vec<i64> = tree
+                .into_ordered_vec(end)
+                .iter()
+                .map(|&i| lines[i].y(end))
+                .collect();
+
+            let mut template: Vec<i64> = lines.iter().filter(|l| l.exp > end).map(|l| l.y(end)).collect();
+            template.sort_unstable();
+
+            assert_eq!(ys, template);
+        }
+    }
+
+    #[test]
+    fn test_random_01() {
+        let mut rng = rng();
+        for _ in 0..200 {
+            let n = 30;
+            let mut lines: Vec<Line> = Vec::with_capacity(n);
+            let mut tree = KineticTree::new(8, failure);
+            for x in 0..60 {
+                SWEEP_X.set(x);
+                // keep the alive lines in order and query them by key
+                let mut alive: Vec<(i64, usize)> = lines
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, l): &(usize, &Line)| l.exp > x)
+                    .map(|(i, l)| (l.y(x), i))
+                    .collect();
+                alive.sort_unstable();
+                for w in alive.windows(2) {
+                    // at a crossing point the order of equal lines is undefined
+                    if alive.iter().filter(|a| a.0 == w[1].0).count() > 1 {
+                        continue;
+                    }
+                    let prev = tree.first_less(x, usize::MAX, lines[w[1].1]);
+                    assert_eq!(lines[prev].y(x), w[0].0);
+                }
+
+                if lines.len() < n {
+                    let line = Line {
+                        y0: rng.random_range(-1000..1000) - rng.random_range(-20..20) * x,
+                        k: rng.random_range(-20..20),
+                        exp: x + rng.random_range(1..40),
+                    };
+                    if alive.iter().all(|a| a.0 != line.y(x)) {
+                        tree.insert(line, lines.len(), x);
+                        lines.push(line);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,32 @@
 #[cfg(test)]
 mod tests {
+    use i_tree::HasId;
+    use i_tree::set::array::IntoArray;
     use i_tree::set::list::SetList;
-    use i_tree::set::sort::SetCollection;
+    use i_tree::set::sort::{KeyValue, SetCollection};
+    use i_tree::set::summary::Summary;
     use i_tree::set::tree::SetTree;
     use rand::prelude::SliceRandom;
-    use rand::rng;
+    use rand::{RngExt, rng};
+    use std::collections::BTreeSet;
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Item {
+        id: usize,
+        key: i32,
+    }
+
+    impl KeyValue<i32> for Item {
+        fn key(&self) -> &i32 {
+            &self.key
+        }
+    }
+
+    impl HasId for Item {
+        fn id(&self) -> usize {
+            self.id
+        }
+    }
 
     #[test]
     fn test_00() {
@@ -95,4 +117,1079 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_where_random_00() {
+        let n = 100;
+        let template: Vec<i32> = (0..n).map(|i| 2 * i).collect();
+        let mut rng = rng();
+        for _ in 0..100 {
+            let mut array = template.clone();
+            array.shuffle(&mut rng);
+            let mut tree = SetTree::new(8);
+            let mut list = SetList::new(array.len());
+            for &val in array.iter().take(n as usize / 2) {
+                tree.insert(val);
+                list.insert(val);
+            }
+
+            for key in -1..2 * n + 1 {
+                for m in 2..5 {
+                    let pred = |v: &i32| v % (2 * m) == 0;
+
+                    let t = tree.first_less_where(&key, pred);
+                    let l = list.first_less_where(&key, pred);
+                    assert_eq!(t == u32::MAX, l == u32::MAX);
+                    if t != u32::MAX {
+                        let tv = unsafe { *tree.value_by_index(t) };
+                        let lv = unsafe { *list.value_by_index(l) };
+                        assert_eq!(tv, lv);
+                        assert!(tv < key);
+                    }
+
+                    let t = tree.first_greater_where(&key, pred);
+                    let l = list.first_greater_where(&key, pred);
+                    assert_eq!(t == u32::MAX, l == u32::MAX);
+                    if t != u32::MAX {
+                        let tv = unsafe { *tree.value_by_index(t) };
+                        let lv = unsafe { *list.value_by_index(l) };
+                        assert_eq!(tv, lv);
+                        assert!(tv > key);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_id_00() {
+        let mut tree = SetTree::with_id_index(8);
+        for i in 0..10 {
+            tree.insert(Item {
+                id: 100 - i as usize,
+                key: i,
+            });
+        }
+        assert_eq!(tree.next_by_id(95).unwrap().key, 6);
+        assert_eq!(tree.prev_by_id(95).unwrap().key, 4);
+        assert!(tree.prev_by_id(100).is_none());
+        assert!(tree.next_by_id(91).is_none());
+
+        assert_eq!(tree.remove_by_id(94).unwrap().key, 6);
+        assert!(tree.position_of(94).is_none());
+        assert_eq!(tree.next_by_id(95).unwrap().key, 7);
+    }
+
+    #[test]
+    fn test_id_01() {
+        let mut tree = SetTree::with_id_index(8);
+        for i in 0..10 {
+            tree.insert(Item {
+                id: i as usize,
+                key: i,
+            });
+        }
+        tree.insert(Item {
+            id: usize::MAX,
+            key: 10,
+        });
+        assert_eq!(tree.next_by_id(9).unwrap().key, 10);
+
+        // the id is changed through get_mut, the index follows it
+        let handle = tree.handle(tree.position_of(3).unwrap()).unwrap();
+        tree.get_mut(handle).unwrap().id = 1 << 40;
+        assert!(tree.position_of(3).is_none());
+        assert_eq!(tree.next_by_id(1 << 40).unwrap().key, 4);
+
+        assert_eq!(tree.remove_by_id(usize::MAX).unwrap().key, 10);
+        assert_eq!(tree.remove_by_id(1 << 40).unwrap().key, 3);
+        assert!(tree.remove_by_id(1 << 40).is_none());
+    }
+
+    #[test]
+    fn test_id_02() {
+        let mut tree = SetTree::with_id_index(8);
+        for key in 0..4 {
+            tree.insert(Item {
+                id: key as usize,
+                key,
+            });
+        }
+        // the ids of the first two values are swapped while both guards are alive
+        let mut items: Vec<_> = tree.iter_mut().collect();
+        items[0].id = 1;
+        items[1].id = 0;
+        items[3].id = 1 << 40;
+        drop(items);
+        assert_eq!(tree.next_by_id(1).unwrap().key, 1);
+        assert_eq!(tree.next_by_id(0).unwrap().key, 2);
+        assert_eq!(tree.prev_by_id(1 << 40).unwrap().key, 2);
+        assert!(tree.position_of(3).is_none());
+
+        // a forgotten guard leaves the new id unknown, the old one no longer finds the value
+        let handle = tree.handle(tree.position_of(2).unwrap()).unwrap();
+        let mut item = tree.get_mut(handle).unwrap();
+        item.id = 7;
+        core::mem::forget(item);
+        assert!(tree.position_of(2).is_none());
+        assert_eq!(tree.remove_by_id(0).unwrap().key, 1);
+    }
+
+    #[test]
+    fn test_id_random_00() {
+        let n = 100;
+        let mut rng = rng();
+        for _ in 0..200 {
+            let mut array: Vec<i32> = (0..n).collect();
+            array.shuffle(&mut rng);
+            let mut tree = SetTree::with_id_index(8);
+            let mut list = SetList::new(array.len());
+            for &i in array.iter() {
+                let item = Item {
+                    id: i as usize,
+                    key: i,
+                };
+                tree.insert(item.clone());
+                list.insert(item);
+            }
+
+            array.shuffle(&mut rng);
+            for &i in array.iter().take(n as usize / 2) {
+                let item = tree.remove_by_id(i as usize).unwrap();
+                assert_eq!(item.key, i);
+                list.delete(&i);
+            }
+
+            for &i in array.iter().skip(n as usize / 2) {
+                let index = tree.position_of(i as usize).unwrap();
+                assert_eq!(unsafe { tree.value_by_index(index) }.key, i);
+
+                // list keeps the rest in order, so neighbors are at l ± 1
+                let l = list.first_index_less(&i) as usize;
+                let len = array.len() / 2;
+                let next = (l + 1 < len).then(|| unsafe { list.value_by_index(l as u32 + 1) });
+                let prev = (l > 0).then(|| unsafe { list.value_by_index(l as u32 - 1) });
+                assert_eq!(tree.next_by_id(i as usize), next);
+                assert_eq!(tree.prev_by_id(i as usize), prev);
+            }
+        }
+    }
+
+    #[test]
+    fn test_handle_00() {
+        let mut tree = SetTree::new(8);
+        for i in 0..10 {
+            tree.insert(i);
+        }
+        let first = tree.handle(tree.first_index_less(&0)).unwrap();
+        let mut values = vec![*tree.get(first).unwrap()];
+        let mut handle = first;
+        while let Some(next) = tree.next(handle) {
+            values.push(*tree.get(next).unwrap());
+            handle = next;
+        }
+        assert_eq!(values, (0..10).collect::<Vec<_>>());
+        assert!(tree.prev(first).is_none());
+        assert_eq!(tree.get(tree.prev(handle).unwrap()), Some(&8));
+
+        let five = tree.handle(tree.first_index_less(&5)).unwrap();
+        *tree.get_mut(five).unwrap() = 5;
+        tree.delete(&5);
+        assert!(tree.get(five).is_none());
+        assert!(tree.get_mut(five).is_none());
+        assert!(tree.next(five).is_none());
+
+        // the freed slot is reused, the old handle stays stale
+        tree.insert(5);
+        assert!(tree.get(five).is_none());
+        assert_eq!(
+            tree.get(tree.handle(tree.first_index_less(&5)).unwrap()),
+            Some(&5)
+        );
+    }
+
+    #[test]
+    fn test_handle_01() {
+        let mut list = SetList::new(8);
+        for i in 0..10 {
+            list.insert(i);
+        }
+        let last = list.handle(9).unwrap();
+        assert_eq!(list.get(last), Some(&9));
+        assert!(list.next(last).is_none());
+        assert!(list.handle(10).is_none());
+        assert!(list.index_before(0).is_none());
+        assert!(list.index_after(9).is_none());
+        assert_eq!(list.get(list.index_before(9).unwrap()), Some(&8));
+
+        list.delete(&0);
+        assert!(list.get(last).is_none());
+
+        // an insert anywhere invalidates every handle of a list
+        let first = list.handle(0).unwrap();
+        list.insert(100);
+        assert!(list.get(first).is_none());
+
+        // a retain that removes nothing keeps them
+        let first = list.handle(0).unwrap();
+        list.retain(|_| true);
+        assert_eq!(list.get(first), Some(&1));
+        list.retain(|&v| v != 5);
+        assert!(list.get(first).is_none());
+    }
+
+    #[test]
+    fn test_handle_02() {
+        let mut tree = SetTree::new(8);
+        for i in 0..4 {
+            tree.insert(i);
+        }
+        let index = tree.first_index_less(&2);
+        tree.delete(&2);
+
+        // indices that are out of range, the nil node or a freed slot
+        for index in [index, 0, 100, u32::MAX - 1, u32::MAX] {
+            assert!(tree.index_after(index).is_none());
+            assert!(tree.index_before(index).is_none());
+        }
+    }
+
+    #[test]
+    fn test_handle_random_00() {
+        let n = 100;
+        let mut rng = rng();
+        for _ in 0..100 {
+            let mut array: Vec<i32> = (0..n).collect();
+            array.shuffle(&mut rng);
+            let mut tree = SetTree::new(8);
+            let mut handles = vec![None; n as usize];
+            for &i in array.iter() {
+                let index = tree.insert(i);
+                handles[i as usize] = tree.handle(index);
+            }
+            let handles: Vec<_> = handles.into_iter().map(Option::unwrap).collect();
+
+            array.shuffle(&mut rng);
+            let (removed, kept) = array.split_at(n as usize / 2);
+            for i in removed.iter() {
+                tree.delete(i);
+            }
+            for &i in removed.iter() {
+                assert!(tree.get(handles[i as usize]).is_none());
+            }
+            for &i in kept.iter() {
+                assert_eq!(tree.get(handles[i as usize]), Some(&i));
+            }
+        }
+    }
+
+    #[test]
+    fn test_cursor_00() {
+        let mut tree = SetTree::new(8);
+        for i in [2, 4, 6] {
+            tree.insert(i);
+        }
+        let first = tree.first_handle().unwrap();
+        let mut cursor = tree.cursor(first);
+        assert_eq!(cursor.current(), Some(&2));
+        assert_eq!(cursor.peek_prev(), None);
+        cursor.move_next();
+        assert_eq!(cursor.current(), Some(&4));
+        assert_eq!(cursor.peek_next(), Some(&6));
+        cursor.move_next();
+        cursor.move_next();
+        assert_eq!(cursor.current(), None);
+
+        let mut cursor = tree.cursor_mut(first);
+        assert!(cursor.insert_after(3));
+        assert!(!cursor.insert_after(4));
+        assert!(cursor.insert_before(1));
+        assert_eq!(cursor.current(), Some(&2));
+        assert_eq!(cursor.remove_current(), Some(2));
+        assert_eq!(cursor.current(), Some(&3));
+        assert_eq!(cursor.peek_prev(), Some(&1));
+    }
+
+    #[test]
+    fn test_cursor_01() {
+        let mut tree = SetTree::new(8);
+        let mut list = SetList::new(8);
+        for key in [1, 2, 2, 3] {
+            tree.insert(Item { id: 0, key });
+            list.insert(Item { id: 0, key });
+        }
+
+        // equal keys are accepted on both sides, the same as insert does
+        let second = tree
+            .handle(tree.first_index_less(&1))
+            .and_then(|h| tree.next(h))
+            .unwrap();
+        let mut cursor = tree.cursor_mut(second);
+        assert!(cursor.insert_before(Item { id: 1, key: 2 }));
+        assert!(cursor.insert_after(Item { id: 2, key: 2 }));
+        assert!(!cursor.insert_after(Item { id: 3, key: 3 }));
+        assert!(!cursor.insert_before(Item { id: 3, key: 1 }));
+
+        let second = list.handle(1).unwrap();
+        let mut cursor = list.cursor_mut(second);
+        assert!(cursor.insert_before(Item { id: 1, key: 2 }));
+        let second = list.handle(2).unwrap();
+        let mut cursor = list.cursor_mut(second);
+        assert!(cursor.insert_after(Item { id: 2, key: 2 }));
+
+        let tree_ids: Vec<(i32, usize)> = tree.iter().map(|item| (item.key, item.id)).collect();
+        let list_ids: Vec<(i32, usize)> = list.iter().map(|item| (item.key, item.id)).collect();
+        assert_eq!(tree_ids, [(1, 0), (2, 1), (2, 0), (2, 2), (2, 0), (3, 0)]);
+        assert_eq!(list_ids, tree_ids);
+    }
+
+    fn cursor_walk<C: SetCollection<i32, i32>>(set: &mut C, ops: &[(u8, i32)]) -> Vec<i32> {
+        let mut cursor = set.cursor_mut(set.first_handle().unwrap());
+        for &(op, key) in ops.iter() {
+            if cursor.current().is_none() {
+                break;
+            }
+            match op {
+                0 => cursor.move_next(),
+                1 => cursor.move_prev(),
+                2 => {
+                    cursor.remove_current();
+                }
+                3 => {
+                    cursor.insert_before(key);
+                }
+                _ => {
+                    cursor.insert_after(key);
+                }
+            }
+        }
+
+        let mut result = Vec::new();
+        let Some(first) = set.first_handle() else {
+            return result;
+        };
+        let mut cursor = set.cursor(first);
+        while let Some(&val) = cursor.current() {
+            result.push(val);
+            cursor.move_next();
+        }
+        result
+    }
+
+    #[test]
+    fn test_cursor_random_00() {
+        let n = 40;
+        let mut rng = rng();
+        for _ in 0..500 {
+            let ops: Vec<(u8, i32)> = (0..100)
+                .map(|_| (rng.random_range(0..5), rng.random_range(0..10 * n)))
+                .collect();
+
+            // model
+            let mut template: Vec<i32> = (0..n).map(|i| 10 * i).collect();
+            let mut pos = 0;
+            for &(op, key) in ops.iter() {
+                if pos >= template.len() {
+                    break;
+                }
+                match op {
+                    0 => pos += 1,
+                    1 => {
+                        if pos == 0 {
+                            break;
+                        }
+                        pos -= 1
+                    }
+                    2 => {
+                        template.remove(pos);
+                    }
+                    3 => {
+                        let fits = key <= template[pos] && (pos == 0 || template[pos - 1] <= key);
+                        if fits {
+                            template.insert(pos, key);
+                            pos += 1;
+                        }
+                    }
+                    _ => {
+                        let fits =
+                            key >= template[pos] && (pos + 1 == template.len() || template[pos + 1] >= key);
+                        if fits {
+                            template.insert(pos + 1, key);
+                        }
+                    }
+                }
+            }
+
+            let mut tree = SetTree::new(8);
+            let mut list = SetList::new(8);
+            for i in 0..n {
+                tree.insert(10 * i);
+                list.insert(10 * i);
+            }
+
+            assert_eq!(cursor_walk(&mut tree, &ops), template);
+            assert_eq!(cursor_walk(&mut list, &ops), template);
+        }
+    }
+
+    fn value_at<C: SetCollection<i32, i32>>(set: &C, index: u32) -> Option<i32> {
+        if index == u32::MAX {
+            None
+        } else {
+            Some(unsafe { *set.value_by_index(index) })
+        }
+    }
+
+    #[test]
+    fn test_bounds_00() {
+        let mut list = SetList::new(8);
+        for i in [10, 20, 30] {
+            list.insert(i);
+        }
+        assert_eq!(value_at(&list, list.first_index_less_strict(&20)), Some(10));
+        assert_eq!(value_at(&list, list.first_index_less(&20)), Some(20));
+        assert_eq!(value_at(&list, list.lower_bound(&20)), Some(20));
+        assert_eq!(value_at(&list, list.upper_bound(&20)), Some(30));
+        assert_eq!(value_at(&list, list.upper_bound(&30)), None);
+        assert_eq!(value_at(&list, list.first_index_less_strict(&10)), None);
+    }
+
+    #[test]
+    fn test_bounds_random_00() {
+        let mut rng = rng();
+        for _ in 0..200 {
+            let mut tree = SetTree::new(8);
+            let mut list = SetList::new(8);
+            let mut values = Vec::new();
+            for _ in 0..rng.random_range(0..40) {
+                let val = 2 * rng.random_range(0..50);
+                if list.get_value(&val).is_none() {
+                    tree.insert(val);
+                    list.insert(val);
+                    values.push(val);
+                }
+            }
+            values.sort_unstable();
+
+            for key in -1..102 {
+                let by = |k: &i32| k.cmp(&key);
+                let expected = [
+                    value_at(&list, list.first_index_less_strict(&key)),
+                    value_at(&list, list.first_index_less_strict_by(by)),
+                    value_at(&list, list.first_index_greater(&key)),
+                    value_at(&list, list.first_index_greater_by(by)),
+                    value_at(&list, list.first_index_greater_or_equal(&key)),
+                    value_at(&list, list.first_index_greater_or_equal_by(by)),
+                    value_at(&list, list.lower_bound(&key)),
+                    value_at(&list, list.upper_bound_by(by)),
+                ];
+                let result = [
+                    value_at(&tree, tree.first_index_less_strict(&key)),
+                    value_at(&tree, tree.first_index_less_strict_by(by)),
+                    value_at(&tree, tree.first_index_greater(&key)),
+                    value_at(&tree, tree.first_index_greater_by(by)),
+                    value_at(&tree, tree.first_index_greater_or_equal(&key)),
+                    value_at(&tree, tree.first_index_greater_or_equal_by(by)),
+                    value_at(&tree, tree.lower_bound(&key)),
+                    value_at(&tree, tree.upper_bound_by(by)),
+                ];
+                assert_eq!(result, expected);
+
+                let less = values.iter().rev().find(|&&v| v < key).copied();
+                let greater = values.iter().find(|&&v| v > key).copied();
+                let greater_or_equal = values.iter().find(|&&v| v >= key).copied();
+                assert_eq!(expected[0], less);
+                assert_eq!(expected[2], greater);
+                assert_eq!(expected[4], greater_or_equal);
+            }
+        }
+    }
+
+    // checks that a bound points to the right end of a run of equal keys
+    fn check_bounds<C: SetCollection<i32, Item>>(set: &C, keys: &[i32], key: i32) {
+        let key_at = |index: u32| (index != u32::MAX).then(|| unsafe { set.value_by_index(index) }.key);
+        let key_after = |index: u32| set.index_after(index).map(|h| set.get(h).unwrap().key);
+        let key_before = |index: u32| set.index_before(index).map(|h| set.get(h).unwrap().key);
+
+        let index = set.first_index_less(&key);
+        assert_eq!(key_at(index), keys.iter().rev().find(|&&k| k <= key).copied());
+        assert!(key_after(index).is_none_or(|k| k > key));
+
+        let index = set.first_index_less_strict(&key);
+        assert_eq!(key_at(index), keys.iter().rev().find(|&&k| k < key).copied());
+        assert!(key_after(index).is_none_or(|k| k >= key));
+
+        let index = set.lower_bound(&key);
+        assert_eq!(key_at(index), keys.iter().find(|&&k| k >= key).copied());
+        assert!(key_before(index).is_none_or(|k| k < key));
+
+        let index = set.upper_bound(&key);
+        assert_eq!(key_at(index), keys.iter().find(|&&k| k > key).copied());
+        assert!(key_before(index).is_none_or(|k| k <= key));
+    }
+
+    #[test]
+    fn test_bounds_random_01() {
+        let mut rng = rng();
+        for _ in 0..200 {
+            let mut tree = SetTree::new(8);
+            let mut list = SetList::new(8);
+            let mut keys = Vec::new();
+            for id in 0..rng.random_range(0..40) {
+                let key = 2 * rng.random_range(0..10);
+                tree.insert(Item { id, key });
+                list.insert(Item { id, key });
+                keys.push(key);
+            }
+            keys.sort_unstable();
+
+            for key in -1..22 {
+                check_bounds(&tree, &keys, key);
+                check_bounds(&list, &keys, key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_00() {
+        let mut tree = SetTree::new(8);
+        for key in [3, 1, 2] {
+            tree.insert(Item { id: 0, key });
+        }
+        for mut item in tree.iter_mut() {
+            item.id = item.key as usize * 10;
+        }
+        let ids: Vec<usize> = tree.iter().map(|item| item.id).collect();
+        assert_eq!(ids, [10, 20, 30]);
+
+        let mut iter = tree.iter();
+        assert_eq!(iter.next().map(|item| item.key), Some(1));
+        assert_eq!(iter.next_back().map(|item| item.key), Some(3));
+        assert_eq!(iter.next().map(|item| item.key), Some(2));
+        assert!(iter.next_back().is_none());
+        assert!(iter.next().is_none());
+
+        let keys: Vec<i32> = tree.into_iter().rev().map(|item| item.key).collect();
+        assert_eq!(keys, [3, 2, 1]);
+    }
+
+    #[test]
+    fn test_iter_01() {
+        let mut tree = SetTree::new(8);
+        for key in 0..20 {
+            tree.insert(Item { id: 0, key });
+        }
+        for key in (0..20).step_by(3) {
+            tree.delete(&key);
+        }
+
+        // all yielded references are alive at once while the iterator keeps stepping
+        let mut iter = tree.iter_mut();
+        let mut items = Vec::new();
+        while let Some(front) = iter.next() {
+            items.push(front);
+            if let Some(back) = iter.next_back() {
+                items.push(back);
+            }
+        }
+        for item in items.iter_mut() {
+            item.id = item.key as usize + 1;
+        }
+        assert!(items.iter().all(|item| item.id == item.key as usize + 1));
+        drop(items);
+
+        let ids: Vec<usize> = tree.iter().map(|item| item.id).collect();
+        let template: Vec<usize> = (0..20).filter(|key| key % 3 != 0).map(|key| key + 1).collect();
+        assert_eq!(ids, template);
+    }
+
+    #[test]
+    fn test_iter_random_00() {
+        let mut rng = rng();
+        for _ in 0..200 {
+            let mut tree = SetTree::new(8);
+            let mut list = SetList::new(8);
+            let mut values = Vec::new();
+            for _ in 0..rng.random_range(0..40) {
+                let val = 2 * rng.random_range(0..50);
+                if list.get_value(&val).is_none() {
+                    tree.insert(val);
+                    list.insert(val);
+                    values.push(val);
+                }
+            }
+            values.sort_unstable();
+
+            assert_eq!(tree.iter().copied().collect::<Vec<_>>(), values);
+            assert_eq!(list.iter().copied().collect::<Vec<_>>(), values);
+            assert!(tree.iter().rev().eq(values.iter().rev()));
+
+            for _ in 0..20 {
+                let a = rng.random_range(-1..102);
+                let b = rng.random_range(-1..102);
+                let template: Vec<i32> = values.iter().copied().filter(|v| (a..b).contains(v)).collect();
+                assert_eq!(tree.range(a..b).copied().collect::<Vec<_>>(), template);
+                assert_eq!(list.range(a..b).copied().collect::<Vec<_>>(), template);
+
+                let template: Vec<i32> = values.iter().copied().filter(|v| (a..=b).contains(v)).collect();
+                assert_eq!(tree.range(a..=b).copied().collect::<Vec<_>>(), template);
+                assert_eq!(list.range(a..=b).copied().collect::<Vec<_>>(), template);
+
+                let template: Vec<i32> = values.iter().copied().filter(|v| *v >= a).collect();
+                assert!(tree.range(a..).rev().eq(template.iter().rev()));
+                assert!(list.range(a..).rev().eq(template.iter().rev()));
+            }
+
+            // take from both ends until they meet
+            let mut iter = tree.iter();
+            let mut result = Vec::new();
+            let mut back = Vec::new();
+            loop {
+                let item = if rng.random_bool(0.5) {
+                    iter.next().inspect(|&&v| result.push(v))
+                } else {
+                    iter.next_back().inspect(|&&v| back.push(v))
+                };
+                if item.is_none() {
+                    break;
+                }
+            }
+            result.extend(back.iter().rev());
+            assert_eq!(result, values);
+
+            assert_eq!(tree.into_sorted_vec(), values);
+            assert_eq!(list.into_sorted_vec(), values);
+        }
+    }
+
+    #[test]
+    fn test_remove_random_00() {
+        let mut rng = rng();
+        for _ in 0..300 {
+            let mut tree = SetTree::new(8);
+            let mut list = SetList::new(8);
+            let mut values = Vec::new();
+            for _ in 0..rng.random_range(0..60) {
+                let val = rng.random_range(0..100);
+                if list.get_value(&val).is_none() {
+                    tree.insert(val);
+                    list.insert(val);
+                    values.push(val);
+                }
+            }
+            values.sort_unstable();
+
+            for _ in 0..10 {
+                match rng.random_range(0..5) {
+                    0 => {
+                        let key = rng.random_range(0..100);
+                        let expected = values.iter().position(|&v| v == key).map(|i| values.remove(i));
+                        assert_eq!(tree.take(&key), expected);
+                        assert_eq!(list.take(&key), expected);
+                    }
+                    1 => {
+                        let expected = if values.is_empty() {
+                            None
+                        } else {
+                            Some(values.remove(0))
+                        };
+                        assert_eq!(tree.pop_first(), expected);
+                        assert_eq!(list.pop_first(), expected);
+                    }
+                    2 => {
+                        let expected = values.pop();
+                        assert_eq!(tree.pop_last(), expected);
+                        assert_eq!(list.pop_last(), expected);
+                    }
+                    3 => {
+                        let m = rng.random_range(2..5);
+                        values.retain(|v| v % m != 0);
+                        tree.retain(|v| v % m != 0);
+                        list.retain(|v| v % m != 0);
+                    }
+                    _ => {
+                        let a = rng.random_range(0..100);
+                        let b = rng.random_range(0..100);
+                        values.retain(|v| !(a..=b).contains(v));
+                        tree.remove_range(a..=b);
+                        list.remove_range(a..=b);
+                    }
+                }
+                assert_eq!(tree.iter().copied().collect::<Vec<_>>(), values);
+                assert_eq!(list.iter().copied().collect::<Vec<_>>(), values);
+            }
+
+            // the tree stays usable after bulk removal
+            for &v in values.iter() {
+                assert_eq!(tree.get_value(&v), Some(&v));
+            }
+            tree.insert(1000);
+            assert_eq!(tree.pop_last(), Some(1000));
+        }
+    }
+
+    #[test]
+    fn test_split_append_random_00() {
+        let mut rng = rng();
+        for _ in 0..200 {
+            let n = rng.random_range(0..50);
+            let mut keys: Vec<i32> = (0..n).map(|i| 2 * i).collect();
+            keys.shuffle(&mut rng);
+            let mut tree = SetTree::with_id_index(8);
+            for &key in keys.iter() {
+                tree.insert(Item {
+                    id: key as usize,
+                    key,
+                });
+            }
+
+            let at = rng.random_range(-1..2 * n + 1);
+            let mut right = tree.split_off(&at);
+            for key in (0..n).map(|i| 2 * i) {
+                let (a, b) = if key < at {
+                    (&tree, &right)
+                } else {
+                    (&right, &tree)
+                };
+                assert!(b.position_of(key as usize).is_none());
+                let index = a.position_of(key as usize).unwrap();
+                assert_eq!(unsafe { a.value_by_index(index) }.key, key);
+            }
+
+            // overlapping ranges fall back to inserts
+            let mut odd = SetTree::with_id_index(8);
+            for key in (0..n).map(|i| 2 * i + 1) {
+                odd.insert(Item {
+                    id: key as usize,
+                    key,
+                });
+            }
+            tree.append(&mut right);
+            tree.append(&mut odd);
+            assert!(right.is_empty() && odd.is_empty());
+            assert!(tree.iter().map(|item| item.key).eq(0..2 * n));
+            for key in 0..2 * n {
+                let item = tree.remove_by_id(key as usize).unwrap();
+                assert_eq!(item.key, key);
+            }
+            assert!(tree.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_algebra_random_00() {
+        let mut rng = rng();
+        for _ in 0..300 {
+            let a: BTreeSet<i32> = (0..rng.random_range(0..30))
+                .map(|_| rng.random_range(0..40))
+                .collect();
+            let b: BTreeSet<i32> = (0..rng.random_range(0..30))
+                .map(|_| rng.random_range(0..40))
+                .collect();
+
+            let tree_a = SetTree::from_sorted(a.iter().copied());
+            let tree_b = SetTree::from_sorted(b.iter().copied());
+            let list_a = SetList::from_sorted(a.iter().copied());
+            let list_b = SetList::from_sorted(b.iter().copied());
+
+            let union: Vec<i32> = a.union(&b).copied().collect();
+            let intersection: Vec<i32> = a.intersection(&b).copied().collect();
+            let difference: Vec<i32> = a.difference(&b).copied().collect();
+            let symmetric: Vec<i32> = a.symmetric_difference(&b).copied().collect();
+
+            assert!(tree_a.union(&tree_b).eq(union.iter()));
+            assert!(tree_a.intersection(&tree_b).eq(intersection.iter()));
+            assert!(tree_a.difference(&tree_b).eq(difference.iter()));
+            assert!(tree_a.symmetric_difference(&tree_b).eq(symmetric.iter()));
+
+            assert!(list_a.union(&list_b).eq(union.iter()));
+            assert!(list_a.intersection(&list_b).eq(intersection.iter()));
+            assert!(list_a.difference(&list_b).eq(difference.iter()));
+            assert!(list_a.symmetric_difference(&list_b).eq(symmetric.iter()));
+
+            let mut tree = SetTree::union_of(&tree_a, &tree_b);
+            assert_eq!(tree.iter().copied().collect::<Vec<_>>(), union);
+            for &v in union.iter() {
+                assert_eq!(tree.get_value(&v), Some(&v));
+            }
+            tree.insert(100);
+            assert_eq!(tree.pop_last(), Some(100));
+
+            assert!(
+                SetTree::intersection_of(&tree_a, &tree_b)
+                    .iter()
+                    .eq(intersection.iter())
+            );
+            assert!(
+                SetTree::difference_of(&tree_a, &tree_b)
+                    .iter()
+                    .eq(difference.iter())
+            );
+            assert!(
+                SetTree::symmetric_difference_of(&tree_a, &tree_b)
+                    .iter()
+                    .eq(symmetric.iter())
+            );
+            assert_eq!(SetList::union_of(&list_a, &list_b).into_sorted_vec(), union);
+            assert_eq!(
+                SetList::intersection_of(&list_a, &list_b).into_sorted_vec(),
+                intersection
+            );
+            assert_eq!(
+                SetList::difference_of(&list_a, &list_b).into_sorted_vec(),
+                difference
+            );
+            assert_eq!(
+                SetList::symmetric_difference_of(&list_a, &list_b).into_sorted_vec(),
+                symmetric
+            );
+        }
+    }
+
+    #[test]
+    fn test_algebra_random_01() {
+        let mut rng = rng();
+        for _ in 0..300 {
+            // equal keys are allowed, ids are unique across both trees
+            let mut next_id = 0;
+            let mut random_tree = |rng: &mut rand::rngs::ThreadRng| {
+                let mut tree = SetTree::with_id_index(8);
+                let mut items = Vec::new();
+                for _ in 0..rng.random_range(0..30) {
+                    let item = Item {
+                        id: next_id,
+                        key: rng.random_range(0..10),
+                    };
+                    next_id += 1;
+                    tree.insert(item.clone());
+                    items.push(item);
+                }
+                // a stable sort keeps equal keys in insertion order, like the tree does
+                items.sort_by_key(|item| item.key);
+                (tree, items)
+            };
+            let (tree_a, items_a) = random_tree(&mut rng);
+            let (tree_b, items_b) = random_tree(&mut rng);
+
+            let mut union: Vec<Item> = tree_a.union(&tree_b).cloned().collect();
+            assert!(union.windows(2).all(|w| w[0].key <= w[1].key));
+            assert_eq!(union.len(), {
+                let mut len = 0;
+                for key in 0..10 {
+                    let a = items_a.iter().filter(|item| item.key == key).count();
+                    let b = items_b.iter().filter(|item| item.key == key).count();
+                    len += a.max(b);
+                }
+                len
+            });
+
+            let mut tree = SetTree::union_of(&tree_a, &tree_b);
+            assert!(tree.iter().eq(union.iter()));
+            tree.insert(Item { id: next_id, key: 5 });
+            let pos = union.partition_point(|item| item.key <= 5);
+            union.insert(pos, Item { id: next_id, key: 5 });
+            assert!(tree.iter().eq(union.iter()));
+
+            for item in union.iter() {
+                assert_eq!(tree.remove_by_id(item.id).as_ref(), Some(item));
+            }
+            assert!(tree.is_empty());
+
+            let mut difference = SetTree::difference_of(&tree_a, &tree_b);
+            let items: Vec<Item> = difference.iter().cloned().collect();
+            for item in items.iter() {
+                assert_eq!(difference.remove_by_id(item.id).as_ref(), Some(item));
+            }
+            assert!(difference.is_empty());
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Stat {
+        count: usize,
+        sum: i64,
+        first: Option<i32>,
+        last: Option<i32>,
+        // values were combined in key order
+        sorted: bool,
+    }
+
+    impl Summary<i32> for Stat {
+        fn identity() -> Self {
+            Stat {
+                count: 0,
+                sum: 0,
+                first: None,
+                last: None,
+                sorted: true,
+            }
+        }
+
+        fn from_value(val: &i32) -> Self {
+            Stat {
+                count: 1,
+                sum: *val as i64,
+                first: Some(*val),
+                last: Some(*val),
+                sorted: true,
+            }
+        }
+
+        fn combine(&self, other: &Self) -> Self {
+            let in_order = match (self.last, other.first) {
+                (Some(a), Some(b)) => a < b,
+                _ => true,
+            };
+            Stat {
+                count: self.count + other.count,
+                sum: self.sum + other.sum,
+                first: self.first.or(other.first),
+                last: other.last.or(self.last),
+                sorted: self.sorted && other.sorted && in_order,
+            }
+        }
+    }
+
+    fn fold_template(values: &[i32], a: i32, b: i32) -> Stat {
+        values
+            .iter()
+            .filter(|v| (a..b).contains(v))
+            .fold(Stat::identity(), |s, v| s.combine(&Stat::from_value(v)))
+    }
+
+    #[test]
+    fn test_summary_random_00() {
+        let mut rng = rng();
+        for _ in 0..300 {
+            let mut tree: SetTree<i32, i32, Stat> = SetTree::with_summary(8);
+            let mut values = BTreeSet::new();
+            for _ in 0..40 {
+                match rng.random_range(0..6) {
+                    0..=2 => {
+                        let val = rng.random_range(0..100);
+                        if values.insert(val) {
+                            tree.insert(val);
+                        }
+                    }
+                    3 => {
+                        let val = rng.random_range(0..100);
+                        values.remove(&val);
+                        tree.delete(&val);
+                    }
+                    4 => {
+                        let a = rng.random_range(0..100);
+                        let b = rng.random_range(0..100);
+                        values.retain(|v| !(a..b).contains(v));
+                        tree.remove_range(a..b);
+                    }
+                    _ => {
+                        let at = rng.random_range(0..100);
+                        let mut right = tree.split_off(&at);
+                        assert_eq!(
+                            right.summary(),
+                            fold_template(&Vec::from_iter(values.iter().copied()), at, 100)
+                        );
+                        tree.append(&mut right);
+                    }
+                }
+
+                let sorted: Vec<i32> = values.iter().copied().collect();
+                assert_eq!(tree.summary(), fold_template(&sorted, 0, 100));
+                for _ in 0..5 {
+                    let a = rng.random_range(-1..101);
+                    let b = rng.random_range(-1..101);
+                    assert_eq!(tree.fold_range(a..b), fold_template(&sorted, a, b));
+                }
+            }
+
+            let sorted: Vec<i32> = values.iter().copied().collect();
+            let built: SetTree<i32, i32, Stat> = SetTree::from_sorted_with_summary(sorted.iter().copied());
+            assert_eq!(built.fold_range(10..90), fold_template(&sorted, 10, 90));
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct IdSum(usize);
+
+    impl Summary<Item> for IdSum {
+        fn identity() -> Self {
+            IdSum(0)
+        }
+
+        fn from_value(val: &Item) -> Self {
+            IdSum(val.id)
+        }
+
+        fn combine(&self, other: &Self) -> Self {
+            IdSum(self.0 + other.0)
+        }
+    }
+
+    #[test]
+    fn test_summary_01() {
+        let mut tree: SetTree<i32, Item, IdSum> = SetTree::with_summary(8);
+        let mut indices = Vec::new();
+        for i in 0..20 {
+            indices.push(tree.insert(Item { id: 1, key: i }));
+        }
+        assert_eq!(tree.summary(), IdSum(20));
+
+        // the summaries are refreshed when the guard is dropped
+        for (i, &index) in indices.iter().enumerate() {
+            let handle = tree.handle(index).unwrap();
+            tree.get_mut(handle).unwrap().id = 10;
+            assert_eq!(tree.summary(), IdSum(20 + 9 * (i + 1)));
+            assert_eq!(tree.fold_range(..=i as i32), IdSum(10 * (i + 1)));
+        }
+
+        let handle = tree.handle(indices[0]).unwrap();
+        unsafe { tree.value_by_index_mut(indices[0]) }.id = 1;
+        assert!(tree.refresh_summary(handle));
+        assert_eq!(tree.summary(), IdSum(191));
+        tree.remove(handle);
+        assert!(!tree.refresh_summary(handle));
+    }
+
+    #[test]
+    fn test_update_key_random_00() {
+        let n = 40;
+        let mut rng = rng();
+        for _ in 0..200 {
+            let mut keys: Vec<i32> = (0..4 * n).collect();
+            keys.shuffle(&mut rng);
+            let mut tree = SetTree::with_id_index(8);
+            let mut stat: SetTree<i32, i32, Stat> = SetTree::with_summary(8);
+            let mut indices = Vec::with_capacity(n as usize);
+            let mut stat_indices = Vec::with_capacity(n as usize);
+            for (id, &key) in keys.iter().take(n as usize).enumerate() {
+                indices.push(tree.insert(Item { id, key }));
+                stat_indices.push(stat.insert(key));
+            }
+            let handles: Vec<_> = indices.iter().map(|&i| tree.handle(i).unwrap()).collect();
+            let stat_handles: Vec<_> = stat_indices.iter().map(|&i| stat.handle(i).unwrap()).collect();
+
+            let mut values: BTreeSet<i32> = keys.iter().take(n as usize).copied().collect();
+            let mut current: Vec<i32> = keys.iter().take(n as usize).copied().collect();
+            for _ in 0..100 {
+                let id = rng.random_range(0..n as usize);
+                let key = rng.random_range(0..4 * n);
+                if values.contains(&key) {
+                    continue;
+                }
+                values.remove(&current[id]);
+                values.insert(key);
+                current[id] = key;
+
+                assert!(tree.update_key(handles[id], |item| item.key = key));
+                assert!(stat.update_key(stat_handles[id], |v| *v = key));
+
+                assert_eq!(tree.position_of(id), Some(indices[id]));
+                assert_eq!(tree.get(handles[id]).map(|item| item.key), Some(key));
+                assert!(tree.iter().map(|item| item.key).eq(values.iter().copied()));
+
+                let sorted: Vec<i32> = values.iter().copied().collect();
+                assert_eq!(stat.summary(), fold_template(&sorted, 0, 4 * n));
+            }
+        }
+    }
 }
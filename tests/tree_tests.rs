@@ -1,12 +1,17 @@
 #[cfg(test)]
+#[allow(
+    clippy::non_canonical_partial_ord_impl,
+    clippy::useless_vec,
+    clippy::needless_range_loop
+)]
 mod tests {
-    use i_tree::ExpiredKey;
     use i_tree::key::array::IntoArray;
     use i_tree::key::exp::KeyExpCollection;
     use i_tree::key::list::KeyExpList;
     use i_tree::key::tree::KeyExpTree;
+    use i_tree::{ExpiredKey, HasId};
     use rand::prelude::SliceRandom;
-    use rand::{rng, RngExt};
+    use rand::{RngExt, rng};
     use std::cmp::Ordering;
 
     struct Task {
@@ -59,6 +64,18 @@ mod tests {
         }
     }
 
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Item {
+        id: usize,
+        val: i32,
+    }
+
+    impl HasId for Item {
+        fn id(&self) -> usize {
+            self.id
+        }
+    }
+
     #[test]
     fn test_00() {
         let mut list = KeyExpList::new(1);
@@ -656,6 +673,364 @@ mod tests {
         assert_eq!(list.into_ordered_vec(0), vec![0, 1, 1, 1, 2]);
     }
 
+    #[test]
+    fn test_21() {
+        let mut list = KeyExpList::new(8);
+        let mut tree = KeyExpTree::new(8);
+
+        for i in 0..8 {
+            list.insert(Key::new(i, 10), i, 0);
+            tree.insert(Key::new(i, 10), i, 0);
+        }
+
+        for i in 0..8 {
+            assert_eq!(list.get_value(0, Key::new(i, 10)), Some(i));
+            assert_eq!(tree.get_value(0, Key::new(i, 10)), Some(i));
+        }
+
+        assert_eq!(list.get_value(0, Key::new(8, 10)), None);
+        assert_eq!(tree.get_value(0, Key::new(8, 10)), None);
+    }
+
+    #[test]
+    fn test_22() {
+        let mut list = KeyExpList::new(8);
+        let mut tree = KeyExpTree::new(8);
+
+        for i in 0..8 {
+            list.insert(Key::new(i, 10), i, 0);
+            tree.insert(Key::new(i, 10), i, 0);
+        }
+
+        // extend
+        assert!(list.update_expiration(5, Key::new(3, 10), Key::new(3, 20)));
+        assert!(tree.update_expiration(5, Key::new(3, 10), Key::new(3, 20)));
+
+        // shorten
+        assert!(list.update_expiration(5, Key::new(6, 10), Key::new(6, 7)));
+        assert!(tree.update_expiration(5, Key::new(6, 10), Key::new(6, 7)));
+
+        // missing
+        assert!(!list.update_expiration(5, Key::new(9, 10), Key::new(9, 20)));
+        assert!(!tree.update_expiration(5, Key::new(9, 10), Key::new(9, 20)));
+
+        assert_eq!(list.get_value(8, Key::new(6, 0)), None);
+        assert_eq!(tree.get_value(8, Key::new(6, 0)), None);
+        assert_eq!(list.get_value(8, Key::new(5, 0)), Some(5));
+        assert_eq!(tree.get_value(8, Key::new(5, 0)), Some(5));
+
+        assert_eq!(list.get_value(15, Key::new(3, 0)), Some(3));
+        assert_eq!(tree.get_value(15, Key::new(3, 0)), Some(3));
+        assert_eq!(list.get_value(15, Key::new(5, 0)), None);
+        assert_eq!(tree.get_value(15, Key::new(5, 0)), None);
+
+        assert_eq!(tree.into_ordered_vec(15), vec![3]);
+        assert_eq!(list.into_ordered_vec(15), vec![3]);
+    }
+
+    #[test]
+    fn test_expire_all_00() {
+        for mask in 0..256u32 {
+            let mut tree = KeyExpTree::new(8);
+            for i in 0..8 {
+                let exp = if mask & (1 << i) != 0 { 1 } else { 10 };
+                tree.insert(Key::new(i, exp), i, 0);
+            }
+            let template: Vec<i32> = (0..8).filter(|i| mask & (1 << i) == 0).collect();
+            assert_eq!(tree.into_ordered_vec(5), template, "mask {}", mask);
+        }
+    }
+
+    #[test]
+    fn test_23() {
+        let mut tree = KeyExpTree::with_id_index(8);
+        for i in 0..10 {
+            let exp = if i == 4 { 5 } else { 10 };
+            tree.insert(
+                Key::new(i, exp),
+                Item {
+                    id: i as usize,
+                    val: i,
+                },
+                0,
+            );
+        }
+
+        assert!(tree.position_of(3).is_some());
+        assert!(tree.position_of(10).is_none());
+
+        assert_eq!(tree.next_by_id(0, 3).unwrap().val, 4);
+        assert_eq!(tree.prev_by_id(0, 3).unwrap().val, 2);
+        assert_eq!(tree.next_by_id(6, 3).unwrap().val, 5);
+        assert_eq!(tree.prev_by_id(6, 5).unwrap().val, 3);
+        assert!(tree.prev_by_id(6, 0).is_none());
+        assert!(tree.next_by_id(6, 9).is_none());
+        assert!(tree.position_of(4).is_none());
+
+        assert_eq!(tree.remove_by_id(6, 5).unwrap().val, 5);
+        assert!(tree.remove_by_id(6, 5).is_none());
+        assert_eq!(tree.next_by_id(6, 3).unwrap().val, 6);
+
+        let vals: Vec<i32> = tree.into_ordered_vec(6).iter().map(|it| it.val).collect();
+        assert_eq!(vals, vec![0, 1, 2, 3, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_24() {
+        let mut list = KeyExpList::new(8);
+        let mut tree = KeyExpTree::new(8);
+
+        for i in 0..10 {
+            let exp = if i % 2 == 0 { 5 } else { 10 };
+            list.insert(Key::new(i, exp), i, 0);
+            tree.insert(Key::new(i, exp), i, 0);
+        }
+
+        let is_even = |v: i32| v % 2 == 0;
+
+        assert_eq!(list.first_less_where(0, -1, Key::new(7, 0), is_even), 6);
+        assert_eq!(tree.first_less_where(0, -1, Key::new(7, 0), is_even), 6);
+        assert_eq!(list.first_greater_where(0, -1, Key::new(6, 0), is_even), 8);
+        assert_eq!(tree.first_greater_where(0, -1, Key::new(6, 0), is_even), 8);
+
+        assert_eq!(list.first_less_where(6, -1, Key::new(7, 0), is_even), -1);
+        assert_eq!(tree.first_less_where(6, -1, Key::new(7, 0), is_even), -1);
+        assert_eq!(list.first_greater_where(6, -1, Key::new(6, 0), |v| v > 7), 9);
+        assert_eq!(tree.first_greater_where(6, -1, Key::new(6, 0), |v| v > 7), 9);
+    }
+
+    #[test]
+    fn test_random_where_00() {
+        let n = 100;
+        let mut rng = rng();
+        for _ in 0..300 {
+            let mut list: KeyExpList<Key, i32, i32> = KeyExpList::new(n);
+            let mut tree: KeyExpTree<Key, i32, i32> = KeyExpTree::new(8);
+            let mut numbers = vec![-1i32; n];
+            for time in 0..200 {
+                let index = rng.random_range(0..n);
+                let val = index as i32;
+                let m = rng.random_range(2..5);
+                let pred = |v: i32| v % m == 0;
+                let key = Key::new(val, 0);
+
+                let l0 = list.first_less_where(time, -1, key, pred);
+                let t0 = tree.first_less_where(time, -1, key, pred);
+                assert_eq!(l0, t0);
+
+                let l1 = list.first_greater_where(time, -1, key, pred);
+                let t1 = tree.first_greater_where(time, -1, key, pred);
+                assert_eq!(l1, t1);
+
+                if numbers[index] <= time {
+                    let exp = time + rng.random_range(1..60);
+                    tree.insert(Key::new(val, exp), val, time);
+                    list.insert(Key::new(val, exp), val, time);
+                    numbers[index] = exp;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_25() {
+        let mut tree = KeyExpTree::with_history(8);
+
+        tree.insert(Key::new(0, 10), 0, 0);
+        tree.insert(Key::new(5, 4), 5, 0);
+        tree.insert(Key::new(3, 20), 3, 2);
+        tree.insert(Key::new(5, 12), 50, 6);
+        tree.update_expiration(8, Key::new(3, 20), Key::new(3, 9));
+
+        assert_eq!(tree.get_value(11, Key::new(0, 0)), None);
+        let history = tree.history().unwrap();
+
+        assert_eq!(history.get_value_at(1, Key::new(3, 0)), None);
+        assert_eq!(history.get_value_at(2, Key::new(3, 0)), Some(3));
+        assert_eq!(history.get_value_at(8, Key::new(3, 0)), Some(3));
+        assert_eq!(history.get_value_at(9, Key::new(3, 0)), None);
+        assert_eq!(history.get_value_at(3, Key::new(5, 0)), Some(5));
+        assert_eq!(history.get_value_at(5, Key::new(5, 0)), None);
+        assert_eq!(history.get_value_at(7, Key::new(5, 0)), Some(50));
+
+        assert_eq!(history.first_less_at(1, -1, Key::new(5, 0)), 0);
+        assert_eq!(history.first_less_at(3, -1, Key::new(5, 0)), 3);
+        assert_eq!(history.first_less_at(10, -1, Key::new(5, 0)), -1);
+
+        assert_eq!(history.iter_at(1).collect::<Vec<_>>(), vec![0, 5]);
+        assert_eq!(history.iter_at(3).collect::<Vec<_>>(), vec![0, 3, 5]);
+        assert_eq!(history.iter_at(5).collect::<Vec<_>>(), vec![0, 3]);
+        assert_eq!(history.iter_at(7).collect::<Vec<_>>(), vec![0, 3, 50]);
+        assert_eq!(history.iter_at(11).collect::<Vec<_>>(), vec![50]);
+    }
+
+    #[test]
+    fn test_26() {
+        let mut tree = KeyExpTree::with_history(8);
+        let mut id_tree = KeyExpTree::with_id_index(8);
+        for i in 0..4 {
+            tree.insert(Key::new(i, 10), i, 0);
+            id_tree.insert(
+                Key::new(i, 10),
+                Item {
+                    id: i as usize,
+                    val: i,
+                },
+                0,
+            );
+        }
+        assert!(tree.history().unwrap().iter_at(0).eq(0..4));
+
+        // a tree without history has no past
+        assert!(id_tree.history().is_none());
+
+        tree.clear();
+        assert_eq!(tree.history().unwrap().iter_at(0).count(), 0);
+    }
+
+    #[test]
+    fn test_27() {
+        let mut tree = KeyExpTree::with_history_and_id_index(8);
+        for i in 0..4 {
+            tree.insert(
+                Key::new(i, 10),
+                Item {
+                    id: i as usize,
+                    val: i,
+                },
+                i,
+            );
+        }
+
+        assert_eq!(tree.remove_by_id(5, 2).unwrap().val, 2);
+        assert!(tree.position_of(2).is_none());
+
+        assert_eq!(
+            tree.history()
+                .unwrap()
+                .get_value_at(4, Key::new(2, 0))
+                .unwrap()
+                .val,
+            2
+        );
+        assert_eq!(tree.history().unwrap().get_value_at(5, Key::new(2, 0)), None);
+        assert_eq!(tree.get_value(5, Key::new(2, 0)), None);
+        assert_eq!(
+            tree.history()
+                .unwrap()
+                .iter_at(3)
+                .map(|item| item.val)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(
+            tree.history()
+                .unwrap()
+                .iter_at(6)
+                .map(|item| item.val)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 3]
+        );
+
+        tree.clear();
+        assert_eq!(tree.history().unwrap().iter_at(3).count(), 0);
+    }
+
+    #[test]
+    fn test_random_id_00() {
+        let n = 64;
+        let mut rng = rng();
+        for _ in 0..200 {
+            let mut order: Vec<i32> = (0..n).collect();
+            order.shuffle(&mut rng);
+            let mut tree = KeyExpTree::with_id_index(8);
+            for &i in order.iter() {
+                let exp = rng.random_range(1..100);
+                tree.insert(
+                    Key::new(i, exp),
+                    Item {
+                        id: i as usize,
+                        val: i,
+                    },
+                    0,
+                );
+            }
+            order.shuffle(&mut rng);
+            let mut time = 0;
+            for &i in order.iter().take(n as usize / 2) {
+                time += 1;
+                tree.remove_by_id(time, i as usize);
+                assert!(tree.position_of(i as usize).is_none());
+            }
+
+            let mut list = KeyExpList::new(n as usize);
+            for &i in order.iter().skip(n as usize / 2) {
+                if tree.position_of(i as usize).is_some() {
+                    let key = Key::new(i, 100);
+                    list.insert(
+                        key,
+                        Item {
+                            id: i as usize,
+                            val: i,
+                        },
+                        0,
+                    );
+                }
+            }
+            let live = list.into_ordered_vec(0);
+
+            // each neighbor query also expires
+            for (j, item) in live.iter().enumerate() {
+                if let Some(next) = tree.next_by_id(time, item.id) {
+                    assert!(next.val > item.val);
+                    assert!(live[j + 1..].iter().any(|it| it.id == next.id));
+                }
+                if let Some(prev) = tree.prev_by_id(time, item.id) {
+                    assert!(prev.val < item.val);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_random_id_01() {
+        let n = 64;
+        let mut rng = rng();
+        for _ in 0..200 {
+            let mut order: Vec<i32> = (0..n).collect();
+            order.shuffle(&mut rng);
+            let mut tree = KeyExpTree::with_id_index(8);
+            for &i in order.iter() {
+                tree.insert(
+                    Key::new(i, 100),
+                    Item {
+                        id: i as usize,
+                        val: i,
+                    },
+                    0,
+                );
+            }
+            let positions: Vec<_> = (0..n).map(|i| tree.position_of(i as usize).unwrap()).collect();
+
+            // a node keeps its index until it is removed itself
+            order.shuffle(&mut rng);
+            let (removed, kept) = order.split_at(n as usize / 2);
+            for &i in removed.iter() {
+                tree.remove_by_id(0, i as usize);
+            }
+            for &i in kept.iter() {
+                assert_eq!(tree.position_of(i as usize), Some(positions[i as usize]));
+            }
+            for &i in kept.iter() {
+                let item = Item {
+                    id: i as usize,
+                    val: i,
+                };
+                assert_eq!(tree.remove_by_id(0, i as usize), Some(item));
+            }
+        }
+    }
+
     #[test]
     fn test_random_00() {
         let mut array = Vec::with_capacity(100);